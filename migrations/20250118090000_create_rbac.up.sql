@@ -0,0 +1,26 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS roles(
+    name VARCHAR(64) PRIMARY KEY,
+    description TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS permissions(
+    name VARCHAR(64) PRIMARY KEY,
+    description TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS role_permissions(
+    role VARCHAR(64) NOT NULL,
+    permission VARCHAR(64) NOT NULL,
+    PRIMARY KEY(role, permission),
+    CONSTRAINT role_permissions_fk_role FOREIGN KEY(role) REFERENCES roles(name) ON DELETE CASCADE,
+    CONSTRAINT role_permissions_fk_permission FOREIGN KEY(permission) REFERENCES permissions(name) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS user_roles(
+    user_id UUID NOT NULL,
+    role VARCHAR(64) NOT NULL,
+    PRIMARY KEY(user_id, role),
+    CONSTRAINT user_roles_fk_user_id FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE,
+    CONSTRAINT user_roles_fk_role FOREIGN KEY(role) REFERENCES roles(name) ON DELETE CASCADE
+);
@@ -0,0 +1,5 @@
+-- Add down migration script here
+DELETE FROM user_roles;
+DELETE FROM role_permissions;
+DELETE FROM permissions;
+DELETE FROM roles;
@@ -0,0 +1,35 @@
+-- Add up migration script here
+INSERT INTO roles(name, description) VALUES
+    ('admin', 'Full access, including user management'),
+    ('editor', 'Manages every post'),
+    ('author', 'Writes and manages their own posts')
+ON CONFLICT (name) DO NOTHING;
+
+INSERT INTO permissions(name, description) VALUES
+    ('posts:create', 'Create posts'),
+    ('posts:update:own', 'Edit own posts'),
+    ('posts:update:any', 'Edit any post'),
+    ('posts:delete:own', 'Delete own posts'),
+    ('posts:delete:any', 'Delete any post'),
+    ('users:manage', 'Manage user accounts')
+ON CONFLICT (name) DO NOTHING;
+
+INSERT INTO role_permissions(role, permission) VALUES
+    ('admin', 'posts:create'),
+    ('admin', 'posts:update:own'),
+    ('admin', 'posts:update:any'),
+    ('admin', 'posts:delete:own'),
+    ('admin', 'posts:delete:any'),
+    ('admin', 'users:manage'),
+    ('editor', 'posts:create'),
+    ('editor', 'posts:update:own'),
+    ('editor', 'posts:update:any'),
+    ('editor', 'posts:delete:own'),
+    ('editor', 'posts:delete:any'),
+    ('author', 'posts:create'),
+    ('author', 'posts:update:own'),
+    ('author', 'posts:delete:own')
+ON CONFLICT DO NOTHING;
+
+-- Existing accounts become authors
+INSERT INTO user_roles(user_id, role) SELECT id, 'author' FROM users ON CONFLICT DO NOTHING;
@@ -0,0 +1,10 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS posts_title_id_idx;
+DROP INDEX IF EXISTS posts_updated_at_id_idx;
+DROP INDEX IF EXISTS posts_created_at_id_idx;
+
+ALTER TABLE posts
+    ALTER COLUMN created_at DROP NOT NULL,
+    ALTER COLUMN created_at DROP DEFAULT,
+    ALTER COLUMN updated_at DROP NOT NULL,
+    ALTER COLUMN updated_at DROP DEFAULT;
@@ -0,0 +1,13 @@
+-- Add up migration script here
+UPDATE posts SET created_at = COALESCE(created_at, NOW()) WHERE created_at IS NULL;
+UPDATE posts SET updated_at = COALESCE(updated_at, created_at) WHERE updated_at IS NULL;
+
+ALTER TABLE posts
+    ALTER COLUMN created_at SET DEFAULT NOW(),
+    ALTER COLUMN created_at SET NOT NULL,
+    ALTER COLUMN updated_at SET DEFAULT NOW(),
+    ALTER COLUMN updated_at SET NOT NULL;
+
+CREATE INDEX IF NOT EXISTS posts_created_at_id_idx ON posts(created_at, id);
+CREATE INDEX IF NOT EXISTS posts_updated_at_id_idx ON posts(updated_at, id);
+CREATE INDEX IF NOT EXISTS posts_title_id_idx ON posts(title, id);
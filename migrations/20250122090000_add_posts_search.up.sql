@@ -0,0 +1,8 @@
+-- Add up migration script here
+ALTER TABLE posts ADD COLUMN IF NOT EXISTS search tsvector
+    GENERATED ALWAYS AS (
+        setweight(to_tsvector('english', title), 'A') ||
+        setweight(to_tsvector('english', content), 'B')
+    ) STORED;
+
+CREATE INDEX IF NOT EXISTS posts_search_idx ON posts USING GIN(search);
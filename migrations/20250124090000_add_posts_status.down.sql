@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS posts_status_published_at_idx;
+ALTER TABLE posts DROP COLUMN IF EXISTS published_at, DROP COLUMN IF EXISTS status;
+DROP TYPE IF EXISTS post_status;
@@ -0,0 +1,11 @@
+-- Add up migration script here
+CREATE TYPE post_status AS ENUM ('draft', 'published', 'scheduled', 'archived');
+
+ALTER TABLE posts
+    ADD COLUMN IF NOT EXISTS status post_status NOT NULL DEFAULT 'draft',
+    ADD COLUMN IF NOT EXISTS published_at TIMESTAMPTZ;
+
+-- Everything written so far was public
+UPDATE posts SET status = 'published', published_at = created_at;
+
+CREATE INDEX IF NOT EXISTS posts_status_published_at_idx ON posts(status, published_at);
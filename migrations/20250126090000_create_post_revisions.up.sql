@@ -0,0 +1,13 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS post_revisions(
+    id UUID PRIMARY KEY,
+    post_id UUID NOT NULL,
+    revision INTEGER NOT NULL,
+    editor_id UUID NOT NULL,
+    title VARCHAR(255) NOT NULL,
+    content TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CONSTRAINT post_revisions_fk_post_id FOREIGN KEY(post_id) REFERENCES posts(id) ON DELETE CASCADE,
+    CONSTRAINT post_revisions_fk_editor_id FOREIGN KEY(editor_id) REFERENCES users(id),
+    CONSTRAINT post_revisions_post_id_revision_key UNIQUE(post_id, revision)
+);
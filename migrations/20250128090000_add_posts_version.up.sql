@@ -0,0 +1,2 @@
+-- Add up migration script here
+ALTER TABLE posts ADD COLUMN IF NOT EXISTS version BIGINT NOT NULL DEFAULT 1;
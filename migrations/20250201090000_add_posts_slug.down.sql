@@ -0,0 +1,10 @@
+-- Add down migration script here
+-- Fails while two posts share a title, rename one of them first
+DROP INDEX IF EXISTS posts_user_id_title_key;
+ALTER TABLE posts ADD CONSTRAINT posts_title_key UNIQUE(title);
+
+DROP TABLE IF EXISTS post_slugs;
+
+ALTER TABLE posts
+    DROP CONSTRAINT IF EXISTS posts_slug_key,
+    DROP COLUMN IF EXISTS slug;
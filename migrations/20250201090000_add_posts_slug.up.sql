@@ -0,0 +1,39 @@
+-- Add up migration script here
+ALTER TABLE posts ADD COLUMN IF NOT EXISTS slug VARCHAR(100);
+
+-- The application transliterates titles, existing posts get an ASCII-only approximation.
+-- Titles were unique so far, slugs that still collide get the start of the post id appended.
+WITH base AS (
+    SELECT id, created_at, COALESCE(
+        NULLIF(trim(both '-' from left(regexp_replace(lower(title), '[^a-z0-9]+', '-', 'g'), 80)), ''),
+        'post'
+    ) AS slug
+    FROM posts
+), numbered AS (
+    SELECT id, slug, row_number() OVER (PARTITION BY slug ORDER BY created_at, id) AS n
+    FROM base
+)
+UPDATE posts
+SET slug = CASE WHEN numbered.n = 1 THEN numbered.slug ELSE numbered.slug || '-' || left(posts.id::text, 8) END
+FROM numbered
+WHERE posts.id = numbered.id AND posts.slug IS NULL;
+
+ALTER TABLE posts
+    ALTER COLUMN slug SET NOT NULL,
+    ADD CONSTRAINT posts_slug_key UNIQUE(slug);
+
+-- Every slug a post was ever known by, old ones redirect to the current one
+CREATE TABLE IF NOT EXISTS post_slugs(
+    slug VARCHAR(100) PRIMARY KEY,
+    post_id UUID NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CONSTRAINT post_slugs_fk_post_id FOREIGN KEY(post_id) REFERENCES posts(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS post_slugs_post_id_idx ON post_slugs(post_id);
+
+INSERT INTO post_slugs(slug, post_id) SELECT slug, id FROM posts ON CONFLICT DO NOTHING;
+
+-- Titles only need to be unique among an author's posts outside the trash
+ALTER TABLE posts DROP CONSTRAINT IF EXISTS posts_title_key;
+CREATE UNIQUE INDEX IF NOT EXISTS posts_user_id_title_key ON posts(user_id, title) WHERE deleted_at IS NULL;
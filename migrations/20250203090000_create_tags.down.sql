@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP FUNCTION IF EXISTS post_tag_names(UUID);
+DROP TABLE IF EXISTS post_tags;
+DROP TABLE IF EXISTS tags;
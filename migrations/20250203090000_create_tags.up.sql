@@ -0,0 +1,24 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS tags(
+    id UUID PRIMARY KEY,
+    name VARCHAR(50) NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS post_tags(
+    post_id UUID NOT NULL,
+    tag_id UUID NOT NULL,
+    PRIMARY KEY(post_id, tag_id),
+    CONSTRAINT post_tags_fk_post_id FOREIGN KEY(post_id) REFERENCES posts(id) ON DELETE CASCADE,
+    CONSTRAINT post_tags_fk_tag_id FOREIGN KEY(tag_id) REFERENCES tags(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS post_tags_tag_id_idx ON post_tags(tag_id);
+
+-- Sorted tag names of a post
+CREATE OR REPLACE FUNCTION post_tag_names(post UUID) RETURNS VARCHAR[] LANGUAGE sql STABLE AS $$
+    SELECT COALESCE(array_agg(t.name ORDER BY t.name), '{}')
+    FROM post_tags pt
+    JOIN tags t ON t.id = pt.tag_id
+    WHERE pt.post_id = post
+$$;
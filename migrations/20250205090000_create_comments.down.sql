@@ -0,0 +1,8 @@
+-- Add down migration script here
+DELETE FROM permissions WHERE name IN ('comments:create', 'comments:moderate');
+
+DROP FUNCTION IF EXISTS post_comment_count(UUID);
+
+ALTER TABLE posts DROP COLUMN IF EXISTS comments_locked;
+
+DROP TABLE IF EXISTS comments;
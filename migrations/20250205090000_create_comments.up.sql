@@ -0,0 +1,41 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS comments(
+    id UUID PRIMARY KEY,
+    post_id UUID NOT NULL,
+    user_id UUID NOT NULL,
+    -- Comment this one replies to, NULL for top-level comments
+    parent_id UUID,
+    -- Number of ancestors, 0 for top-level comments
+    depth INTEGER NOT NULL DEFAULT 0,
+    content TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    -- Deleted comments stay as placeholders so their replies keep their place in the thread
+    deleted_at TIMESTAMPTZ,
+    CONSTRAINT comments_fk_post_id FOREIGN KEY(post_id) REFERENCES posts(id) ON DELETE CASCADE,
+    CONSTRAINT comments_fk_user_id FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE,
+    CONSTRAINT comments_fk_parent_id FOREIGN KEY(parent_id) REFERENCES comments(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS comments_post_id_created_at_idx ON comments(post_id, created_at);
+CREATE INDEX IF NOT EXISTS comments_parent_id_idx ON comments(parent_id);
+
+ALTER TABLE posts ADD COLUMN IF NOT EXISTS comments_locked BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Comments of a post that are not deleted
+CREATE OR REPLACE FUNCTION post_comment_count(post UUID) RETURNS BIGINT LANGUAGE sql STABLE AS $$
+    SELECT COUNT(*) FROM comments WHERE post_id = post AND deleted_at IS NULL
+$$;
+
+INSERT INTO permissions(name, description) VALUES
+    ('comments:create', 'Comment on posts'),
+    ('comments:moderate', 'Delete any comment')
+ON CONFLICT (name) DO NOTHING;
+
+INSERT INTO role_permissions(role, permission) VALUES
+    ('admin', 'comments:create'),
+    ('admin', 'comments:moderate'),
+    ('editor', 'comments:create'),
+    ('editor', 'comments:moderate'),
+    ('author', 'comments:create')
+ON CONFLICT DO NOTHING;
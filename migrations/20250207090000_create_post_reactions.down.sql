@@ -0,0 +1,12 @@
+-- Add down migration script here
+DROP FUNCTION IF EXISTS post_reacted(UUID, UUID);
+DROP FUNCTION IF EXISTS post_reactions(UUID);
+
+DROP TRIGGER IF EXISTS post_reactions_count ON post_reactions;
+DROP FUNCTION IF EXISTS post_reactions_count();
+
+DROP INDEX IF EXISTS posts_reaction_count_id_idx;
+ALTER TABLE posts DROP COLUMN IF EXISTS reaction_count;
+
+DROP TABLE IF EXISTS post_reactions;
+DROP TYPE IF EXISTS reaction_kind;
@@ -0,0 +1,47 @@
+-- Add up migration script here
+CREATE TYPE reaction_kind AS ENUM ('like', 'love', 'laugh', 'wow', 'sad', 'angry');
+
+-- A user may react to a post with several kinds, each once
+CREATE TABLE IF NOT EXISTS post_reactions(
+    post_id UUID NOT NULL,
+    user_id UUID NOT NULL,
+    kind reaction_kind NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY(post_id, user_id, kind),
+    CONSTRAINT post_reactions_fk_post_id FOREIGN KEY(post_id) REFERENCES posts(id) ON DELETE CASCADE,
+    CONSTRAINT post_reactions_fk_user_id FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+-- Total number of reactions, kept next to the post so listings can sort by popularity
+ALTER TABLE posts ADD COLUMN IF NOT EXISTS reaction_count BIGINT NOT NULL DEFAULT 0;
+
+CREATE INDEX IF NOT EXISTS posts_reaction_count_id_idx ON posts(reaction_count, id);
+
+-- The count follows every added and removed reaction, including ones removed along with
+-- their user
+CREATE OR REPLACE FUNCTION post_reactions_count() RETURNS TRIGGER LANGUAGE plpgsql AS $$
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        UPDATE posts SET reaction_count = reaction_count + 1 WHERE id = NEW.post_id;
+    ELSE
+        UPDATE posts SET reaction_count = reaction_count - 1 WHERE id = OLD.post_id;
+    END IF;
+    RETURN NULL;
+END
+$$;
+
+CREATE TRIGGER post_reactions_count AFTER INSERT OR DELETE ON post_reactions
+    FOR EACH ROW EXECUTE FUNCTION post_reactions_count();
+
+-- Number of reactions of each kind a post got, kinds nobody used are left out
+CREATE OR REPLACE FUNCTION post_reactions(post UUID) RETURNS JSONB LANGUAGE sql STABLE AS $$
+    SELECT COALESCE(jsonb_object_agg(kind, count), '{}')
+    FROM (SELECT kind, COUNT(*) AS count FROM post_reactions WHERE post_id = post GROUP BY kind) r
+$$;
+
+-- Kinds a user reacted to a post with
+CREATE OR REPLACE FUNCTION post_reacted(post UUID, reactor UUID) RETURNS reaction_kind[] LANGUAGE sql STABLE AS $$
+    SELECT COALESCE(array_agg(kind ORDER BY kind), '{}')
+    FROM post_reactions
+    WHERE post_id = post AND user_id = reactor
+$$;
@@ -0,0 +1,11 @@
+-- Add up migration script here
+
+-- Failed logins per account (key is the user id) and per client address (key is the IP)
+CREATE TABLE IF NOT EXISTS login_throttles(
+    scope VARCHAR(10) NOT NULL CHECK (scope IN ('account', 'ip')),
+    key VARCHAR(255) NOT NULL,
+    failures INT NOT NULL DEFAULT 0,
+    last_failed_at TIMESTAMPTZ,
+    locked_until TIMESTAMPTZ,
+    PRIMARY KEY(scope, key)
+);
@@ -0,0 +1,5 @@
+-- Add up migration script here
+
+-- Set when an already rotated token was exchanged once more inside the reuse grace window.
+-- Any presentation after that revokes the family.
+ALTER TABLE refresh_tokens ADD COLUMN reissued_at TIMESTAMPTZ;
@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::model::Comment;
+
+// Deepest a reply may be nested, top-level comments are at depth 0
+pub const MAX_COMMENT_DEPTH: i32 = 10;
+
+// A comment with the replies to it, oldest first
+#[derive(Debug, Serialize)]
+pub struct CommentNode {
+    #[serde(flatten)]
+    pub comment: Comment,
+    pub replies: Vec<CommentNode>,
+}
+
+// Arrange the comments of a post, oldest first, into threads. Deleted comments are dropped
+// unless a reply still hangs off them.
+pub fn comment_tree(comments: Vec<Comment>) -> Vec<CommentNode> {
+    let mut children: HashMap<Option<Uuid>, Vec<Comment>> = HashMap::new();
+    for comment in comments {
+        children.entry(comment.parent_id).or_default().push(comment);
+    }
+    replies_to(&mut children, None)
+}
+
+fn replies_to(
+    children: &mut HashMap<Option<Uuid>, Vec<Comment>>,
+    parent: Option<Uuid>,
+) -> Vec<CommentNode> {
+    children
+        .remove(&parent)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|comment| {
+            let replies = replies_to(children, Some(comment.id));
+            if comment.deleted && replies.is_empty() {
+                None
+            } else {
+                Some(CommentNode { comment, replies })
+            }
+        })
+        .collect()
+}
+
+// Threads as a single list, every comment followed by its replies
+pub fn flatten_tree(tree: Vec<CommentNode>) -> Vec<Comment> {
+    let mut comments = Vec::new();
+    push_flattened(&mut comments, tree);
+    comments
+}
+
+fn push_flattened(comments: &mut Vec<Comment>, tree: Vec<CommentNode>) {
+    for node in tree {
+        comments.push(node.comment);
+        push_flattened(comments, node.replies);
+    }
+}
+
+// Number of comments in threads, not counting the placeholders of deleted ones
+pub fn comment_count(tree: &[CommentNode]) -> usize {
+    tree.iter()
+        .map(|node| usize::from(!node.comment.deleted) + comment_count(&node.replies))
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+
+    fn comment(parent: Option<&Comment>, deleted: bool) -> Comment {
+        Comment {
+            id: Uuid::new_v4(),
+            post_id: Uuid::nil(),
+            user_id: Uuid::nil(),
+            parent_id: parent.map(|p| p.id),
+            depth: parent.map_or(0, |p| p.depth + 1),
+            content: (!deleted).then(|| "text".to_string()),
+            deleted,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn deleted_placeholders_are_kept_but_not_counted() {
+        let deleted_parent = comment(None, true);
+        let reply = comment(Some(&deleted_parent), false);
+        let deleted_leaf = comment(None, true);
+        let top = comment(None, false);
+        let deleted_reply = comment(Some(&top), true);
+
+        let tree = comment_tree(vec![
+            deleted_parent,
+            reply,
+            deleted_leaf,
+            top,
+            deleted_reply,
+        ]);
+        assert_eq!(tree.len(), 2);
+        assert!(tree[0].comment.deleted);
+        assert_eq!(tree[0].replies.len(), 1);
+        assert!(tree[1].replies.is_empty());
+
+        assert_eq!(comment_count(&tree), 2);
+        assert_eq!(flatten_tree(tree).len(), 3);
+    }
+}
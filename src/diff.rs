@@ -0,0 +1,32 @@
+use serde::Serialize;
+use similar::{ChangeTag, TextDiff};
+
+// One line of a line-level diff. Line numbers start at 1 and refer to the side the line
+// exists on.
+#[derive(Debug, Serialize)]
+pub struct LineChange {
+    pub op: &'static str,
+    pub old_line: Option<usize>,
+    pub new_line: Option<usize>,
+    pub text: String,
+}
+
+// Lines are compared without their terminators, so a missing final newline is no change
+pub fn line_diff(old: &str, new: &str) -> Vec<LineChange> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+
+    TextDiff::from_slices(&old, &new)
+        .iter_all_changes()
+        .map(|change| LineChange {
+            op: match change.tag() {
+                ChangeTag::Equal => "equal",
+                ChangeTag::Delete => "delete",
+                ChangeTag::Insert => "insert",
+            },
+            old_line: change.old_index().map(|i| i + 1),
+            new_line: change.new_index().map(|i| i + 1),
+            text: change.value().to_string(),
+        })
+        .collect()
+}
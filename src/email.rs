@@ -0,0 +1,339 @@
+use std::{
+    fmt, fs,
+    sync::{Arc, Mutex},
+};
+
+use lettre::{
+    message::{Mailbox, MultiPart},
+    transport::smtp::authentication::Credentials,
+    FileTransport, Message, SmtpTransport, Transport,
+};
+
+use crate::settings::{EmailSettings, EmailTransport, SmtpSecurity};
+
+// Message to a single recipient, with a plain text and an HTML body
+#[derive(Debug, Clone)]
+pub struct EmailMessage {
+    pub to: String,
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+}
+
+#[derive(Debug)]
+pub struct EmailError(pub String);
+
+impl fmt::Display for EmailError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "email error: {}", self.0)
+    }
+}
+
+impl std::error::Error for EmailError {}
+
+// Delivers outgoing mail. Sending may block, handlers go through `send_in_background`.
+pub trait EmailSender: Send + Sync {
+    fn send(&self, message: &EmailMessage) -> Result<(), EmailError>;
+}
+
+// Sender picked by `email.transport`, which has to be set explicitly
+pub fn email_sender(settings: &EmailSettings) -> Result<Arc<dyn EmailSender>, EmailError> {
+    Ok(match settings.transport {
+        None => {
+            return Err(EmailError(
+                "`email.transport` must be set to smtp, file or log".to_string(),
+            ))
+        }
+        Some(EmailTransport::Log) => {
+            log::warn!("email.transport is log: emails are not delivered, only for development");
+            Arc::new(LogSender)
+        }
+        Some(EmailTransport::Smtp) => Arc::new(SmtpSender::new(settings)?),
+        Some(EmailTransport::File) => Arc::new(FileSender::new(settings)?),
+    })
+}
+
+// Logs that a message would have been sent instead of delivering it. The body carries
+// verification and reset links, which must not end up in logs, so it is left out.
+pub struct LogSender;
+
+impl EmailSender for LogSender {
+    fn send(&self, message: &EmailMessage) -> Result<(), EmailError> {
+        log::info!(
+            "email to {}: {} (body not logged)",
+            message.to,
+            message.subject
+        );
+        Ok(())
+    }
+}
+
+// Delivers messages through an SMTP server
+pub struct SmtpSender {
+    from: Mailbox,
+    transport: SmtpTransport,
+}
+
+impl SmtpSender {
+    pub fn new(settings: &EmailSettings) -> Result<SmtpSender, EmailError> {
+        let host = settings.smtp_host.as_str();
+        let builder = match settings.smtp_security {
+            SmtpSecurity::None => SmtpTransport::builder_dangerous(host),
+            SmtpSecurity::StartTls => SmtpTransport::starttls_relay(host).map_err(smtp_error)?,
+            SmtpSecurity::Tls => SmtpTransport::relay(host).map_err(smtp_error)?,
+        };
+        let builder = builder.port(settings.smtp_port);
+        let builder = match &settings.smtp_username {
+            Some(username) => builder.credentials(Credentials::new(
+                username.clone(),
+                settings.smtp_password.clone().unwrap_or_default(),
+            )),
+            None => builder,
+        };
+
+        Ok(SmtpSender {
+            from: settings.from.clone(),
+            transport: builder.build(),
+        })
+    }
+}
+
+impl EmailSender for SmtpSender {
+    fn send(&self, message: &EmailMessage) -> Result<(), EmailError> {
+        let message = mime_message(&self.from, message)?;
+        self.transport.send(&message).map_err(smtp_error)?;
+        Ok(())
+    }
+}
+
+// Writes every message as an .eml file into a directory, for development
+pub struct FileSender {
+    from: Mailbox,
+    transport: FileTransport,
+}
+
+impl FileSender {
+    pub fn new(settings: &EmailSettings) -> Result<FileSender, EmailError> {
+        let dir = &settings.file_dir;
+        fs::create_dir_all(dir).map_err(|e| {
+            EmailError(format!(
+                "failed to create mail directory {}: {}",
+                dir.display(),
+                e
+            ))
+        })?;
+
+        Ok(FileSender {
+            from: settings.from.clone(),
+            transport: FileTransport::new(dir),
+        })
+    }
+}
+
+impl EmailSender for FileSender {
+    fn send(&self, message: &EmailMessage) -> Result<(), EmailError> {
+        let message = mime_message(&self.from, message)?;
+        self.transport
+            .send(&message)
+            .map_err(|e| EmailError(format!("failed to write message: {}", e)))?;
+        Ok(())
+    }
+}
+
+// Keeps messages in memory so tests can look at what was sent
+#[derive(Default)]
+pub struct MemorySender {
+    sent: Mutex<Vec<EmailMessage>>,
+}
+
+impl MemorySender {
+    // Messages sent so far, oldest first
+    pub fn sent(&self) -> Vec<EmailMessage> {
+        self.sent.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+impl EmailSender for MemorySender {
+    fn send(&self, message: &EmailMessage) -> Result<(), EmailError> {
+        self.sent
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(message.clone());
+        Ok(())
+    }
+}
+
+// Send a message without holding up the response, failures are only logged
+pub fn send_in_background(sender: Arc<dyn EmailSender>, message: EmailMessage) {
+    actix_web::rt::task::spawn_blocking(move || {
+        if let Err(e) = sender.send(&message) {
+            log::error!("sending email to {} failed: {}", message.to, e);
+        }
+    });
+}
+
+// Subject and bodies of an email, with `{{name}}` placeholders for per-message variables
+pub struct EmailTemplate {
+    pub subject: &'static str,
+    pub text: &'static str,
+    pub html: &'static str,
+}
+
+pub const VERIFY_EMAIL: EmailTemplate = EmailTemplate {
+    subject: "Verify your email address",
+    text: include_str!("../templates/email/verify_email.txt"),
+    html: include_str!("../templates/email/verify_email.html"),
+};
+
+pub const RESET_PASSWORD: EmailTemplate = EmailTemplate {
+    subject: "Reset your password",
+    text: include_str!("../templates/email/reset_password.txt"),
+    html: include_str!("../templates/email/reset_password.html"),
+};
+
+impl EmailTemplate {
+    // Message to `to` with the placeholders filled in. Values are HTML escaped in the HTML
+    // body, placeholders without a value are left empty.
+    pub fn render(&self, to: &str, vars: &[(&str, &str)]) -> EmailMessage {
+        EmailMessage {
+            to: to.to_string(),
+            subject: fill(self.subject, vars, |value| value.to_string()),
+            text: fill(self.text, vars, |value| value.to_string()),
+            html: fill(self.html, vars, escape_html),
+        }
+    }
+}
+
+fn fill(template: &str, vars: &[(&str, &str)], encode: fn(&str) -> String) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            break;
+        };
+        out.push_str(&rest[..start]);
+        let name = rest[start + 2..start + end].trim();
+        if let Some((_, value)) = vars.iter().find(|(var, _)| *var == name) {
+            out.push_str(&encode(value));
+        }
+        rest = &rest[start + end + 2..];
+    }
+
+    out.push_str(rest);
+    out
+}
+
+fn escape_html(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn mime_message(from: &Mailbox, message: &EmailMessage) -> Result<Message, EmailError> {
+    let to: Mailbox = message
+        .to
+        .parse()
+        .map_err(|e| EmailError(format!("invalid recipient {}: {}", message.to, e)))?;
+
+    Message::builder()
+        .from(from.clone())
+        .to(to)
+        .subject(message.subject.clone())
+        .multipart(MultiPart::alternative_plain_html(
+            message.text.clone(),
+            message.html.clone(),
+        ))
+        .map_err(|e| EmailError(format!("failed to build message: {}", e)))
+}
+
+fn smtp_error(e: lettre::transport::smtp::Error) -> EmailError {
+    EmailError(format!("smtp: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity(value: &str) -> String {
+        value.to_string()
+    }
+
+    #[test]
+    fn fills_placeholders() {
+        assert_eq!(
+            fill(
+                "Hi {{name}}, see {{ link }}.",
+                &[("name", "Ada"), ("link", "https://x.io")],
+                identity
+            ),
+            "Hi Ada, see https://x.io."
+        );
+    }
+
+    #[test]
+    fn leaves_missing_placeholders_empty() {
+        assert_eq!(fill("Hi {{name}}!", &[], identity), "Hi !");
+        assert_eq!(fill("{{a}}{{b}}", &[("b", "2")], identity), "2");
+    }
+
+    #[test]
+    fn keeps_unterminated_placeholders() {
+        assert_eq!(fill("Hi {{name", &[("name", "Ada")], identity), "Hi {{name");
+        assert_eq!(fill("no placeholders", &[], identity), "no placeholders");
+    }
+
+    #[test]
+    fn escapes_html() {
+        assert_eq!(
+            escape_html(r#"<a href="x">Tom & Jerry's</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&#39;s&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn escapes_values_only_in_the_html_body() {
+        let message = VERIFY_EMAIL.render(
+            "ada@x.io",
+            &[
+                ("name", "<b>Ada</b>"),
+                ("link", "https://x.io/verify-email?token=a&b"),
+                ("ttl_hours", "24"),
+            ],
+        );
+
+        assert_eq!(message.to, "ada@x.io");
+        assert!(message.text.contains("Hi <b>Ada</b>,"));
+        assert!(message.text.contains("https://x.io/verify-email?token=a&b"));
+        assert!(message.html.contains("Hi &lt;b&gt;Ada&lt;/b&gt;,"));
+        assert!(message
+            .html
+            .contains(r#"href="https://x.io/verify-email?token=a&amp;b""#));
+        assert!(!message.html.contains("{{"));
+    }
+
+    #[test]
+    fn memory_sender_keeps_sent_messages_in_order() {
+        let sender = MemorySender::default();
+        for to in ["a@x.io", "b@x.io"] {
+            let message = RESET_PASSWORD.render(to, &[("name", "Ada"), ("link", "https://x.io")]);
+            sender.send(&message).unwrap();
+        }
+
+        let sent = sender.sent();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0].to, "a@x.io");
+        assert_eq!(sent[1].to, "b@x.io");
+        assert_eq!(sent[0].subject, RESET_PASSWORD.subject);
+        assert!(sent[0].text.contains("https://x.io"));
+    }
+}
@@ -0,0 +1,257 @@
+use std::fmt;
+
+use actix_web::{
+    http::{header::RETRY_AFTER, StatusCode},
+    HttpResponse, ResponseError,
+};
+use jsonwebtoken::errors::ErrorKind;
+use serde::Serialize;
+use serde_json::json;
+
+use crate::mfa::MfaError;
+use crate::session::RefreshError;
+
+// Every failed request is answered with the same JSON envelope:
+//
+//     {"status": "fail", "code": "not_found", "message": "Post with given id not found!"}
+//
+// `status` is "fail" for client errors and "error" for server errors, `code` is one of the
+// stable machine-readable codes returned by `AppError::code`, and `details` lists the
+// offending fields of a `validation_failed` error. Server errors never carry the underlying
+// error text, it is only logged.
+#[derive(Debug)]
+pub enum AppError {
+    Validation(Vec<FieldError>),
+    Unauthorized(&'static str, String),
+    Forbidden(String),
+    // The caller's email address has to be verified first
+    EmailUnverified(String),
+    NotFound(String),
+    Conflict(String),
+    PreconditionFailed(String),
+    PayloadTooLarge(String),
+    UnsupportedMediaType(String),
+    // Code, message and seconds until the client may try again
+    TooManyRequests(&'static str, String, i64),
+    Database(sqlx::Error),
+    Internal(String),
+}
+
+// Field level validation failure
+#[derive(Debug, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl AppError {
+    pub fn validation(field: &str, message: &str) -> AppError {
+        AppError::Validation(vec![FieldError {
+            field: field.to_string(),
+            message: message.to_string(),
+        }])
+    }
+
+    // Unique violations become a conflict with the given message, anything else a database error
+    pub fn conflict_on_unique(e: sqlx::Error, message: &str) -> AppError {
+        match e.as_database_error() {
+            Some(db_error) if db_error.is_unique_violation() => {
+                AppError::Conflict(message.to_string())
+            }
+            _ => AppError::Database(e),
+        }
+    }
+
+    // Stable error code reported to clients
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::Validation(_) => "validation_failed",
+            AppError::Unauthorized(code, _) => code,
+            AppError::Forbidden(_) => "forbidden",
+            AppError::EmailUnverified(_) => "email_unverified",
+            AppError::NotFound(_) => "not_found",
+            AppError::Conflict(_) => "conflict",
+            AppError::PreconditionFailed(_) => "precondition_failed",
+            AppError::PayloadTooLarge(_) => "payload_too_large",
+            AppError::UnsupportedMediaType(_) => "unsupported_media_type",
+            AppError::TooManyRequests(code, ..) => code,
+            AppError::Database(sqlx::Error::RowNotFound) => "not_found",
+            AppError::Database(_) | AppError::Internal(_) => "internal_error",
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            AppError::Validation(_) => "Request validation failed".to_string(),
+            AppError::Unauthorized(_, message)
+            | AppError::Forbidden(message)
+            | AppError::EmailUnverified(message)
+            | AppError::NotFound(message)
+            | AppError::Conflict(message)
+            | AppError::PreconditionFailed(message)
+            | AppError::PayloadTooLarge(message)
+            | AppError::UnsupportedMediaType(message)
+            | AppError::TooManyRequests(_, message, _) => message.clone(),
+            AppError::Database(sqlx::Error::RowNotFound) => "Resource not found".to_string(),
+            AppError::Database(_) | AppError::Internal(_) => {
+                "Something went wrong. Please try again later!".to_string()
+            }
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::Database(e) => write!(f, "database error: {}", e),
+            AppError::Internal(message) => write!(f, "internal error: {}", message),
+            other => write!(f, "{}: {}", other.code(), other.message()),
+        }
+    }
+}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::Unauthorized(..) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) | AppError::EmailUnverified(_) => StatusCode::FORBIDDEN,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            AppError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            AppError::TooManyRequests(..) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::Database(sqlx::Error::RowNotFound) => StatusCode::NOT_FOUND,
+            AppError::Database(_) | AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        if status.is_server_error() {
+            log::error!("{}", self);
+        }
+
+        let mut body = json!({
+            "status": if status.is_server_error() { "error" } else { "fail" },
+            "code": self.code(),
+            "message": self.message(),
+        });
+        if let AppError::Validation(details) = self {
+            body["details"] = json!(details);
+        }
+
+        let mut response = HttpResponse::build(status);
+        if let AppError::TooManyRequests(_, _, retry_after) = self {
+            response.insert_header((RETRY_AFTER, retry_after.to_string()));
+        }
+        response.json(body)
+    }
+}
+
+impl From<sqlx::Error> for AppError {
+    fn from(e: sqlx::Error) -> Self {
+        AppError::Database(e)
+    }
+}
+
+// Decoding failures are the client's fault, signing failures are ours
+impl From<jsonwebtoken::errors::Error> for AppError {
+    fn from(e: jsonwebtoken::errors::Error) -> Self {
+        match e.kind() {
+            ErrorKind::ExpiredSignature => {
+                AppError::Unauthorized("token_expired", "Access token has expired".to_string())
+            }
+            ErrorKind::InvalidToken
+            | ErrorKind::InvalidSignature
+            | ErrorKind::InvalidAlgorithm
+            | ErrorKind::InvalidIssuer
+            | ErrorKind::InvalidAudience
+            | ErrorKind::InvalidSubject
+            | ErrorKind::ImmatureSignature
+            | ErrorKind::MissingRequiredClaim(_)
+            | ErrorKind::Base64(_)
+            | ErrorKind::Json(_)
+            | ErrorKind::Utf8(_) => AppError::Unauthorized(
+                "token_invalid",
+                "Invalid token. Please try again!".to_string(),
+            ),
+            _ => AppError::Internal(format!("token error: {}", e)),
+        }
+    }
+}
+
+// A password that does not match is a failed login, anything else a broken hash
+impl From<argon2::password_hash::Error> for AppError {
+    fn from(e: argon2::password_hash::Error) -> Self {
+        match e {
+            argon2::password_hash::Error::Password => AppError::Unauthorized(
+                "invalid_credentials",
+                "Invalid credentials. Please try again!".to_string(),
+            ),
+            other => AppError::Internal(format!("password hash error: {}", other)),
+        }
+    }
+}
+
+impl From<RefreshError> for AppError {
+    fn from(e: RefreshError) -> Self {
+        match e {
+            RefreshError::Invalid => AppError::Unauthorized(
+                "refresh_token_invalid",
+                "Invalid or expired refresh token".to_string(),
+            ),
+            RefreshError::Reused => AppError::Unauthorized(
+                "refresh_token_reused",
+                "Refresh token reuse detected. Please log in again!".to_string(),
+            ),
+            RefreshError::Database(e) => AppError::Database(e),
+        }
+    }
+}
+
+impl From<MfaError> for AppError {
+    fn from(e: MfaError) -> Self {
+        match e {
+            MfaError::Invalid => AppError::Unauthorized(
+                "mfa_token_invalid",
+                "Invalid or expired MFA token. Please log in again!".to_string(),
+            ),
+            MfaError::WrongCode => AppError::Unauthorized(
+                "mfa_code_invalid",
+                "Invalid authentication code. Please try again!".to_string(),
+            ),
+            MfaError::Database(e) => AppError::Database(e),
+        }
+    }
+}
+
+// Bodies that are too large or not JSON at all are rejected before validation
+impl From<actix_web::error::JsonPayloadError> for AppError {
+    fn from(e: actix_web::error::JsonPayloadError) -> Self {
+        use actix_web::error::JsonPayloadError;
+
+        match e {
+            JsonPayloadError::Overflow { .. } | JsonPayloadError::OverflowKnownLength { .. } => {
+                AppError::PayloadTooLarge(e.to_string())
+            }
+            JsonPayloadError::ContentType => {
+                AppError::UnsupportedMediaType("Content type must be application/json".to_string())
+            }
+            other => AppError::validation("body", &other.to_string()),
+        }
+    }
+}
+
+impl From<actix_web::error::PathError> for AppError {
+    fn from(e: actix_web::error::PathError) -> Self {
+        AppError::validation("path", &e.to_string())
+    }
+}
+
+impl From<actix_web::error::QueryPayloadError> for AppError {
+    fn from(e: actix_web::error::QueryPayloadError) -> Self {
+        AppError::validation("query", &e.to_string())
+    }
+}
@@ -0,0 +1,65 @@
+use actix_web::{
+    http::header::{
+        CacheControl, CacheDirective, ETag, EntityTag, Header, IfMatch, IfNoneMatch, IF_MATCH, VARY,
+    },
+    HttpRequest, HttpResponseBuilder,
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::{error::AppError, model::Post};
+
+// Strong ETag of a post as the caller sees it, `<version>-<digest>`. Comments and reactions
+// change the representation without being edits, so they change the digest of the counts and
+// the caller's reactions but not the version.
+pub fn post_etag(post: &Post) -> EntityTag {
+    let counts = format!(
+        "{}|{:?}|{:?}",
+        post.comment_count, post.reactions.0, post.reacted
+    );
+    let digest = format!("{:x}", Sha256::digest(counts.as_bytes()));
+    EntityTag::new_strong(format!("{}-{}", post.version, &digest[..16]))
+}
+
+// Tag a single post response. The tag covers the caller's own reactions, so shared caches
+// must not hand it to anyone else.
+pub fn tag_post(builder: &mut HttpResponseBuilder, post: &Post) {
+    builder
+        .insert_header(ETag(post_etag(post)))
+        .insert_header(CacheControl(vec![CacheDirective::Private]))
+        .insert_header((VARY, "Authorization, Cookie"));
+}
+
+// Versions an If-Match header accepts, None when it is absent or `*`. Only the version part of
+// a tag counts, comments written since the client fetched the post don't fail its write. Weak
+// tags never match and tags that are not ours match nothing, so a write carrying only those
+// fails.
+pub fn expected_versions(req: &HttpRequest) -> Result<Option<Vec<i64>>, AppError> {
+    let if_match = IfMatch::parse(req)
+        .map_err(|_| AppError::validation("If-Match", "is not a valid entity tag list"))?;
+
+    match if_match {
+        IfMatch::Any => Ok(None),
+        IfMatch::Items(tags) if tags.is_empty() && !req.headers().contains_key(IF_MATCH) => {
+            Ok(None)
+        }
+        IfMatch::Items(tags) => Ok(Some(
+            tags.iter()
+                .filter(|tag| !tag.weak)
+                .filter_map(|tag| tag.tag().split('-').next()?.parse().ok())
+                .collect(),
+        )),
+    }
+}
+
+// Whether If-None-Match says the client already has this state of the post
+pub fn not_modified(req: &HttpRequest, post: &Post) -> bool {
+    match IfNoneMatch::parse(req) {
+        Ok(IfNoneMatch::Any) => true,
+        Ok(IfNoneMatch::Items(tags)) => {
+            let current = post_etag(post);
+            tags.iter().any(|tag| tag.weak_eq(&current))
+        }
+        Err(_) => false,
+    }
+}
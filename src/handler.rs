@@ -1,3 +1,10 @@
 pub mod auth;
+pub mod comments;
 pub mod generic;
 pub mod posts;
+pub mod reactions;
+pub mod revisions;
+pub mod tags;
+pub mod totp;
+pub mod trash;
+pub mod users;
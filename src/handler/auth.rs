@@ -1,2 +1,5 @@
 pub mod authenticate;
+pub mod jwks;
+pub mod password;
 pub mod register;
+pub mod verification;
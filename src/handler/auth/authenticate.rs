@@ -3,83 +3,233 @@ use actix_web::{
         time::{Duration, OffsetDateTime},
         Cookie,
     },
-    post, web, HttpResponse, Responder,
+    post, web, HttpRequest, HttpResponse, Responder,
 };
-use argon2::PasswordHash;
+use chrono::{TimeZone, Utc};
 use serde_json::json;
 
 use crate::{
-    model::UserLogin,
-    queries::get_user_with_email,
-    utils::{generate_access_token, generate_refresh_token, verify_hashed_password},
+    error::AppError,
+    mfa::{complete_mfa_challenge, pending_mfa_user, start_mfa_challenge, MfaError},
+    model::{MfaLogin, RefreshRequest, UserLogin},
+    queries::{get_user_roles, get_user_with_email, is_email_verified},
+    session::{
+        issue_refresh_token, revoke_refresh_token, rotate_refresh_token, IssuedRefreshToken,
+    },
+    settings::{TokenTransport, UnverifiedAccess},
+    throttle::{
+        check_login_allowed, record_login_failure, reset_account_throttle, ACCOUNT_SCOPE, IP_SCOPE,
+    },
+    utils::{
+        access_claim, client_ip, hash_token, refresh_token_cookie, sign_claim,
+        verify_dummy_password, verify_hashed_password,
+    },
     AppState,
 };
 
+// Failed attempts are counted per account and per client address, either has to wait after
+// a failure and is locked for a while after too many
 #[post("/login")]
 pub async fn user_login_handler(
     state: web::Data<AppState>,
+    req: HttpRequest,
     body: web::Json<UserLogin>,
-) -> actix_web::Result<impl Responder> {
+) -> Result<impl Responder, AppError> {
     let pool = &state.pool;
+    let jwt = &state.settings.jwt;
+    let ip = client_ip(&req, state.settings.server.trust_forwarded_for);
+    check_login_allowed(&state, IP_SCOPE, &ip).await?;
+
+    let user = match get_user_with_email(pool, &body.email).await {
+        Ok(user) => Some(user),
+        Err(sqlx::Error::RowNotFound) => None,
+        Err(e) => return Err(e.into()),
+    };
+
+    // Unknown addresses are throttled and answered like accounts with a wrong password, so
+    // responses don't tell which addresses have an account
+    let account = match &user {
+        Some(user) => user.id.to_string(),
+        None => format!("unknown:{}", hash_token(&body.email)),
+    };
+    check_login_allowed(&state, ACCOUNT_SCOPE, &account).await?;
 
-    match get_user_with_email(pool, &body.email).await {
-        Ok(user) => {
-            let db_password = PasswordHash::new(&user.password)
-                .map_err(|_| {
-                    actix_web::error::ErrorInternalServerError("Error parsing hashed password")
-                })?
-                .to_string();
-
-            if let Err(_) = verify_hashed_password(&body.password, &db_password) {
-                return Err(actix_web::error::ErrorUnauthorized(
-                    json!({"error": "Invalid credentials. Please try agian!"}),
-                ));
-            }
-
-            let access_token =
-                generate_access_token(&user.id.to_string(), "secret").map_err(|_| {
-                    actix_web::error::ErrorInternalServerError(
-                        json!({"error": "Error generating access token!"}),
-                    )
-                })?;
-
-            let refresh_token =
-                generate_refresh_token(&user.id.to_string(), "secret").map_err(|_| {
-                    actix_web::error::ErrorInternalServerError(
-                        json!({"error": "Error generating access token!"}),
-                    )
-                })?;
-
-            let refresh_token_cookie = Cookie::build("refresh_token", refresh_token)
-                .http_only(true)
-                .path("/")
-                .expires(OffsetDateTime::now_utc() + Duration::days(365))
-                .finish();
-
-            let access_token_cookie = Cookie::build("access_token", access_token)
-                .http_only(true)
-                .path("/")
-                .finish();
-
-            Ok(HttpResponse::Ok()
-                .cookie(access_token_cookie)
-                .cookie(refresh_token_cookie)
-                .json(json!({
-                    "status": "success",
-                    "message": "Use logged in successfully",
-                })))
+    let verified = match &user {
+        Some(user) => verify_hashed_password(&body.password, &user.password),
+        None => verify_dummy_password(&body.password),
+    };
+    if let Err(e) = verified {
+        if e == argon2::password_hash::Error::Password {
+            record_login_failure(&state, ACCOUNT_SCOPE, &account).await?;
+            record_login_failure(&state, IP_SCOPE, &ip).await?;
         }
-        Err(_) => {
-            return Err(actix_web::error::ErrorNotFound(json!({
-                "status": "fail",
-                "message": "User with provided email does not exists. Please try again!"
-            })))
+        return Err(e.into());
+    }
+    let user = user.ok_or(argon2::password_hash::Error::Password)?;
+
+    if user.email_verified_at.is_none()
+        && state.settings.auth.unverified_access == UnverifiedAccess::None
+    {
+        return Err(AppError::EmailUnverified(
+            "Verify your email address before logging in!".to_string(),
+        ));
+    }
+
+    // With two-factor authentication the login is finished at /login/mfa
+    if user.totp_enabled_at.is_some() {
+        let mfa_token =
+            start_mfa_challenge(pool, &user.id, state.settings.auth.mfa_token_ttl_secs).await?;
+        return Ok(HttpResponse::Ok().json(json!({
+            "status": "success",
+            "message": "Enter the code of your authenticator app or a recovery code",
+            "mfa_required": true,
+            "mfa_token": mfa_token.token,
+            "mfa_token_expires_at": mfa_token.expires_at
+        })));
+    }
+
+    // Failures are only forgotten once the whole login succeeded
+    reset_account_throttle(&state, &user.id).await?;
+    let refresh_token = issue_refresh_token(pool, &user.id, jwt.refresh_token_ttl_days).await?;
+
+    session_response(
+        &state,
+        &refresh_token,
+        body.include_tokens,
+        "User logged in successfully",
+    )
+    .await
+}
+
+// Second step of a login with two-factor authentication. Wrong codes count as failed logins
+// of the account and the client address, so the back-off and lockout of the password step
+// also hold up guessing codes.
+#[post("/login/mfa")]
+pub async fn mfa_login_handler(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    body: web::Json<MfaLogin>,
+) -> Result<impl Responder, AppError> {
+    let pool = &state.pool;
+    let ip = client_ip(&req, state.settings.server.trust_forwarded_for);
+    check_login_allowed(&state, IP_SCOPE, &ip).await?;
+
+    let account = pending_mfa_user(pool, &body.mfa_token).await?.to_string();
+    check_login_allowed(&state, ACCOUNT_SCOPE, &account).await?;
+
+    let user_id = match complete_mfa_challenge(pool, &body.mfa_token, &body.code).await {
+        Ok(user_id) => user_id,
+        Err(MfaError::WrongCode) => {
+            record_login_failure(&state, ACCOUNT_SCOPE, &account).await?;
+            record_login_failure(&state, IP_SCOPE, &ip).await?;
+            return Err(MfaError::WrongCode.into());
         }
+        Err(e) => return Err(e.into()),
+    };
+    reset_account_throttle(&state, &user_id).await?;
+
+    let refresh_token =
+        issue_refresh_token(pool, &user_id, state.settings.jwt.refresh_token_ttl_days).await?;
+
+    session_response(
+        &state,
+        &refresh_token,
+        body.include_tokens,
+        "User logged in successfully",
+    )
+    .await
+}
+
+// Exchange a refresh token for a new access/refresh token pair
+#[post("/refresh")]
+pub async fn token_refresh_handler(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    body: Option<web::Json<RefreshRequest>>,
+) -> Result<impl Responder, AppError> {
+    let body = body.map(web::Json::into_inner).unwrap_or_default();
+    let refresh_token = body
+        .refresh_token
+        .or_else(|| req.cookie("refresh_token").map(|c| c.value().to_string()))
+        .ok_or_else(|| {
+            AppError::Unauthorized("refresh_token_missing", "Missing refresh token".to_string())
+        })?;
+
+    let new_refresh_token = rotate_refresh_token(
+        &state.pool,
+        &refresh_token,
+        state.settings.jwt.refresh_token_ttl_days,
+        state.settings.jwt.refresh_token_reuse_grace_secs,
+    )
+    .await?;
+
+    session_response(
+        &state,
+        &new_refresh_token,
+        body.include_tokens,
+        "Session refreshed successfully",
+    )
+    .await
+}
+
+// Hand out a fresh access/refresh pair and report when both tokens expire. Tokens go into
+// the JSON body when the client asks for it or cannot use cookies, otherwise into cookies.
+async fn session_response(
+    state: &AppState,
+    refresh_token: &IssuedRefreshToken,
+    include_tokens: bool,
+    message: &str,
+) -> Result<HttpResponse, AppError> {
+    let email_verified = is_email_verified(&state.pool, &refresh_token.user_id).await?;
+    let roles = get_user_roles(&state.pool, &refresh_token.user_id).await?;
+    let claim = access_claim(
+        &refresh_token.user_id.to_string(),
+        roles,
+        email_verified,
+        &state.settings.jwt,
+    );
+    let access_token = sign_claim(&claim, &state.keys)?;
+
+    let mut body = json!({
+        "status": "success",
+        "message": message,
+        "access_token_expires_at": Utc.timestamp_opt(claim.exp as i64, 0).single(),
+        "access_token_expires_in": claim.exp.saturating_sub(claim.iat),
+        "refresh_token_expires_at": refresh_token.expires_at,
+    });
+
+    if include_tokens || state.settings.auth.token_transport == TokenTransport::Header {
+        body["token_type"] = json!("Bearer");
+        body["access_token"] = json!(access_token);
+        body["refresh_token"] = json!(refresh_token.token);
+        return Ok(HttpResponse::Ok().json(body));
     }
+
+    let access_token_cookie = Cookie::build("access_token", access_token)
+        .http_only(true)
+        .path("/")
+        .finish();
+
+    Ok(HttpResponse::Ok()
+        .cookie(access_token_cookie)
+        .cookie(refresh_token_cookie(refresh_token))
+        .json(body))
 }
 
 #[post("/logout")]
-pub async fn user_logout_handler() -> actix_web::Result<impl Responder> {
+pub async fn user_logout_handler(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    body: Option<web::Json<RefreshRequest>>,
+) -> Result<impl Responder, AppError> {
+    let refresh_token = body
+        .and_then(|body| body.into_inner().refresh_token)
+        .or_else(|| req.cookie("refresh_token").map(|c| c.value().to_string()));
+
+    if let Some(token) = refresh_token {
+        revoke_refresh_token(&state.pool, &token).await?;
+    }
+
     let mut access_cookie = Cookie::build("access_token", "")
         .http_only(true)
         .path("/")
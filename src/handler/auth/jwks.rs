@@ -0,0 +1,11 @@
+use actix_web::{get, http::header, web, HttpResponse, Responder};
+
+use crate::AppState;
+
+// Public keys other services verify our access tokens with
+#[get("/.well-known/jwks.json")]
+pub async fn jwks_handler(state: web::Data<AppState>) -> impl Responder {
+    HttpResponse::Ok()
+        .insert_header((header::CACHE_CONTROL, "public, max-age=300"))
+        .json(state.keys.jwk_set())
+}
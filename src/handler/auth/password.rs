@@ -0,0 +1,50 @@
+use actix_web::{post, web, HttpResponse, Responder};
+use serde_json::json;
+
+use crate::{
+    error::AppError,
+    model::{ForgotPasswordRequest, ResetPasswordRequest},
+    password_reset::{reset_password, send_password_reset_email},
+    queries::get_user_with_email,
+    utils::generate_hash_password,
+    AppState,
+};
+
+// Email a password reset link. The response is the same whether or not an account uses
+// the address, so it can't be used to find out which addresses exist.
+#[post("/forgot-password")]
+pub async fn forgot_password_handler(
+    state: web::Data<AppState>,
+    body: web::Json<ForgotPasswordRequest>,
+) -> Result<impl Responder, AppError> {
+    match get_user_with_email(&state.pool, &body.email).await {
+        Ok(user) => send_password_reset_email(&state, &user.id, &user.name, &user.email).await?,
+        Err(sqlx::Error::RowNotFound) => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    Ok(HttpResponse::Accepted().json(json!({
+        "status": "success",
+        "message": "If an account uses the address, a password reset email is on its way"
+    })))
+}
+
+// Choose a new password with the token from a reset email. Every session of the account
+// ends, it has to log in again.
+#[post("/reset-password")]
+pub async fn reset_password_handler(
+    state: web::Data<AppState>,
+    body: web::Json<ResetPasswordRequest>,
+) -> Result<impl Responder, AppError> {
+    body.validate()?;
+
+    let hashed_password = generate_hash_password(&body.password)?;
+    reset_password(&state, &body.token, &hashed_password)
+        .await?
+        .ok_or_else(|| AppError::validation("token", "is invalid or has expired"))?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "status": "success",
+        "message": "Password reset successfully. Please log in again."
+    })))
+}
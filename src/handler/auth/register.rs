@@ -3,40 +3,44 @@ use serde_json::json;
 use uuid::Uuid;
 
 use crate::{
-    model::UserRegistration, queries::user_registration, utils::generate_hash_password, AppState,
+    error::AppError,
+    model::UserRegistration,
+    queries::{assign_role, user_registration},
+    rbac::DEFAULT_ROLE,
+    utils::generate_hash_password,
+    verification::{issue_verification_token, send_verification_email},
+    AppState,
 };
 
 #[post("/register")]
 pub async fn user_registration_handler(
     state: web::Data<AppState>,
     body: web::Json<UserRegistration>,
-) -> actix_web::Result<impl Responder> {
+) -> Result<impl Responder, AppError> {
     let pool = &state.pool;
+    body.validate()?;
 
     let id = Uuid::new_v4();
-    let hashed_password = generate_hash_password(&body.password).map_err(|e| {
-        actix_web::error::ErrorInternalServerError(format!("Failed to hash password: {}", e))
-    })?;
+    let hashed_password = generate_hash_password(&body.password)?;
 
-    match user_registration(pool, &id, &body.name, &body.email, &hashed_password).await {
-        Ok(user) => Ok(HttpResponse::Created().json(json!({
-            "status": "success",
-            "message": "User created successfully!",
-            "user": user
-        }))),
-        Err(err) => {
-            if let Some(pg_error) = err.as_database_error() {
-                if pg_error.is_unique_violation() {
-                    return Err(actix_web::error::ErrorConflict(json!({
-                    "status": "fail",
-                    "message": "Email already exists. Please try again!"
-                    })));
-                }
-            }
-            Err(actix_web::error::ErrorInternalServerError(json!({
-            "status": "fail",
-            "message": format!("Database error: {}", err)
-            })))
-        }
-    }
+    let mut tx = pool.begin().await?;
+    let user = user_registration(&mut tx, &id, &body.name, &body.email, &hashed_password)
+        .await
+        .map_err(|e| AppError::conflict_on_unique(e, "Email already exists. Please try again!"))?;
+    assign_role(&mut tx, &id, DEFAULT_ROLE).await?;
+    let token = issue_verification_token(
+        &mut tx,
+        &id,
+        state.settings.auth.email_verification_ttl_hours,
+    )
+    .await?;
+    tx.commit().await?;
+
+    send_verification_email(&state, &user.name, &user.email, &token);
+
+    Ok(HttpResponse::Created().json(json!({
+        "status": "success",
+        "message": "User created successfully! Check your email to verify your address.",
+        "user": user
+    })))
 }
@@ -0,0 +1,58 @@
+use actix_web::{post, web, HttpResponse, Responder};
+use serde_json::json;
+
+use crate::{
+    error::AppError,
+    model::{ResendVerificationRequest, VerifyEmailRequest},
+    queries::{consume_verification_token, get_user_with_email},
+    utils::hash_token,
+    verification::{issue_verification_token, may_resend_verification, send_verification_email},
+    AppState,
+};
+
+// Verify the email address of the account a token was sent to
+#[post("/verify-email")]
+pub async fn verify_email_handler(
+    state: web::Data<AppState>,
+    body: web::Json<VerifyEmailRequest>,
+) -> Result<impl Responder, AppError> {
+    consume_verification_token(&state.pool, &hash_token(&body.token))
+        .await?
+        .ok_or_else(|| AppError::validation("token", "is invalid or has expired"))?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "status": "success",
+        "message": "Email address verified successfully"
+    })))
+}
+
+// Send another verification link. The response is the same whether or not the address
+// belongs to an unverified account, so it can't be used to find out which addresses exist.
+#[post("/resend-verification")]
+pub async fn resend_verification_handler(
+    state: web::Data<AppState>,
+    body: web::Json<ResendVerificationRequest>,
+) -> Result<impl Responder, AppError> {
+    match get_user_with_email(&state.pool, &body.email).await {
+        Ok(user) if user.email_verified_at.is_none() => {
+            if may_resend_verification(&state, &user.id).await? {
+                let mut tx = state.pool.begin().await?;
+                let token = issue_verification_token(
+                    &mut tx,
+                    &user.id,
+                    state.settings.auth.email_verification_ttl_hours,
+                )
+                .await?;
+                tx.commit().await?;
+                send_verification_email(&state, &user.name, &user.email, &token);
+            }
+        }
+        Ok(_) | Err(sqlx::Error::RowNotFound) => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    Ok(HttpResponse::Accepted().json(json!({
+        "status": "success",
+        "message": "If the address belongs to an unverified account, a new verification email is on its way"
+    })))
+}
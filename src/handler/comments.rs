@@ -0,0 +1,220 @@
+use actix_web::web::ReqData;
+use actix_web::{delete, get, patch, post, web, HttpResponse, Responder};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::comments::{comment_count, comment_tree, flatten_tree, MAX_COMMENT_DEPTH};
+use crate::error::AppError;
+use crate::handler::posts::post_response;
+use crate::model::{Claim, CommentListQuery, CommentView, NewComment, UpdateComment};
+use crate::queries::{
+    create_comment, delete_comment, get_comment, get_comments, get_post, set_comments_locked,
+    update_comment,
+};
+use crate::rbac::{
+    Permissions, COMMENTS_CREATE, COMMENTS_MODERATE, POSTS_UPDATE_ANY, POSTS_UPDATE_OWN,
+};
+use crate::utils::claim_user_id;
+use crate::AppState;
+
+// List the comments of a post as threads, or with `?view=flat` as one list in thread order.
+// Comments are visible to whoever can read the post.
+#[get("/posts/{id}/comments")]
+pub async fn get_comments_handler(
+    state: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    query: web::Query<CommentListQuery>,
+    req: Option<ReqData<Claim>>,
+) -> Result<impl Responder, AppError> {
+    let pool = &state.pool;
+    let id = path.into_inner();
+    let user_id = claim_user_id(req)?;
+    get_post(pool, &id, &user_id)
+        .await?
+        .ok_or_else(post_not_found)?;
+
+    let tree = comment_tree(get_comments(pool, &id).await?);
+    let result = comment_count(&tree);
+    let comments = match query.view.unwrap_or_default() {
+        CommentView::Tree => json!(tree),
+        CommentView::Flat => json!(flatten_tree(tree)),
+    };
+
+    Ok(HttpResponse::Ok().json(json!({
+        "status": "success",
+        "result": result,
+        "comments": comments
+    })))
+}
+
+// Comment on a post, or reply to one of its comments
+#[post("/posts/{id}/comments")]
+pub async fn create_comment_handler(
+    state: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    body: web::Json<NewComment>,
+    req: Option<ReqData<Claim>>,
+    permissions: Permissions,
+) -> Result<impl Responder, AppError> {
+    let pool = &state.pool;
+    let post_id = path.into_inner();
+    permissions.require(COMMENTS_CREATE)?;
+    body.validate()?;
+    let user_id = claim_user_id(req)?;
+    get_post(pool, &post_id, &user_id)
+        .await?
+        .ok_or_else(post_not_found)?;
+
+    let depth = match body.parent_id {
+        Some(parent_id) => {
+            let parent = get_comment(pool, &parent_id)
+                .await?
+                .filter(|parent| parent.post_id == post_id && !parent.deleted)
+                .ok_or_else(|| {
+                    AppError::NotFound("Parent comment not found on this post!".to_string())
+                })?;
+            if parent.depth >= MAX_COMMENT_DEPTH {
+                return Err(AppError::validation(
+                    "parent_id",
+                    &format!(
+                        "replies may only be nested {} levels deep",
+                        MAX_COMMENT_DEPTH
+                    ),
+                ));
+            }
+            parent.depth + 1
+        }
+        None => 0,
+    };
+
+    let comment = create_comment(
+        pool,
+        &Uuid::new_v4(),
+        &post_id,
+        &user_id,
+        body.parent_id,
+        depth,
+        &body.content,
+    )
+    .await?
+    .ok_or_else(comments_locked)?;
+
+    Ok(HttpResponse::Created().json(json!({
+        "status": "success",
+        "comment": comment
+    })))
+}
+
+// Edit a comment, only its author may
+#[patch("/comments/{id}")]
+pub async fn edit_comment_handler(
+    state: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    body: web::Json<UpdateComment>,
+    req: Option<ReqData<Claim>>,
+) -> Result<impl Responder, AppError> {
+    let pool = &state.pool;
+    let id = path.into_inner();
+    body.validate()?;
+    let user_id = claim_user_id(req)?;
+
+    let comment = get_comment(pool, &id)
+        .await?
+        .filter(|comment| !comment.deleted)
+        .ok_or_else(comment_not_found)?;
+    get_post(pool, &comment.post_id, &user_id)
+        .await?
+        .ok_or_else(comment_not_found)?;
+    if comment.user_id != user_id {
+        return Err(AppError::Forbidden(
+            "Only its author may edit a comment!".to_string(),
+        ));
+    }
+
+    let comment = update_comment(pool, &id, &body.content)
+        .await?
+        .ok_or_else(comments_locked)?;
+    Ok(HttpResponse::Ok().json(json!({
+        "status": "success",
+        "comment": comment
+    })))
+}
+
+// Delete a comment: its author, the post's author and moderators may
+#[delete("/comments/{id}")]
+pub async fn delete_comment_handler(
+    state: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    req: Option<ReqData<Claim>>,
+    permissions: Permissions,
+) -> Result<impl Responder, AppError> {
+    let pool = &state.pool;
+    let id = path.into_inner();
+    let user_id = claim_user_id(req)?;
+
+    let comment = get_comment(pool, &id)
+        .await?
+        .filter(|comment| !comment.deleted)
+        .ok_or_else(comment_not_found)?;
+    let post = get_post(pool, &comment.post_id, &user_id)
+        .await?
+        .ok_or_else(comment_not_found)?;
+    if comment.user_id != user_id && post.user_id != user_id && !permissions.has(COMMENTS_MODERATE)
+    {
+        return Err(AppError::Forbidden(
+            "You are not allowed to delete this comment!".to_string(),
+        ));
+    }
+
+    delete_comment(pool, &id).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+// Stop new comments on a post and edits of the existing ones
+#[post("/posts/{id}/comments/lock")]
+pub async fn lock_comments_handler(
+    state: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    req: Option<ReqData<Claim>>,
+    permissions: Permissions,
+) -> Result<impl Responder, AppError> {
+    change_comments_lock(&state, path.into_inner(), req, &permissions, true).await
+}
+
+// Allow comments on a post again
+#[post("/posts/{id}/comments/unlock")]
+pub async fn unlock_comments_handler(
+    state: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    req: Option<ReqData<Claim>>,
+    permissions: Permissions,
+) -> Result<impl Responder, AppError> {
+    change_comments_lock(&state, path.into_inner(), req, &permissions, false).await
+}
+
+async fn change_comments_lock(
+    state: &AppState,
+    id: Uuid,
+    req: Option<ReqData<Claim>>,
+    permissions: &Permissions,
+    locked: bool,
+) -> Result<HttpResponse, AppError> {
+    let any = permissions.scope(POSTS_UPDATE_OWN, POSTS_UPDATE_ANY)?;
+    let user_id = claim_user_id(req)?;
+
+    let post = set_comments_locked(&state.pool, &id, &user_id, any, locked).await?;
+    Ok(post_response(HttpResponse::Ok(), post))
+}
+
+fn post_not_found() -> AppError {
+    AppError::NotFound("Post with given id not found!".to_string())
+}
+
+fn comment_not_found() -> AppError {
+    AppError::NotFound("Comment with given id not found!".to_string())
+}
+
+fn comments_locked() -> AppError {
+    AppError::Conflict("Comments on this post are locked!".to_string())
+}
@@ -1,31 +1,171 @@
+use actix_web::http::header::LOCATION;
 use actix_web::web::ReqData;
-use actix_web::{delete, error, get, patch, post, web, HttpResponse, Responder};
-use chrono::Utc;
+use actix_web::{
+    delete, get, patch, post, web, HttpRequest, HttpResponse, HttpResponseBuilder, Responder,
+};
+use chrono::{DateTime, Utc};
 use serde_json::json;
 use uuid::Uuid;
 
-use crate::model::{Claim, NewPost, UpdatePost};
-use crate::queries::{create_post, delete_post, get_posts, update_post};
-use crate::utils::parse_uuid;
+use crate::error::AppError;
+use crate::etag::{expected_versions, not_modified, tag_post};
+use crate::model::{
+    Claim, NewPost, Post, PostListQuery, PostQuery, PostSearchQuery, PostStatus, PublishPost,
+    UpdatePost,
+};
+use crate::queries::{
+    create_post, delete_post, find_post_slug, get_post, get_post_with_author, get_posts,
+    search_posts, set_post_status, update_post, TITLE_TAKEN,
+};
+use crate::rbac::{
+    Permissions, POSTS_CREATE, POSTS_DELETE_ANY, POSTS_DELETE_OWN, POSTS_UPDATE_ANY,
+    POSTS_UPDATE_OWN,
+};
+use crate::tags::TagQuery;
+use crate::utils::claim_user_id;
 use crate::AppState;
 
-//retrive posts from db
+//retrive posts from db, one page at a time
 
 #[get("/posts")]
-pub async fn get_posts_handler(state: web::Data<AppState>) -> actix_web::Result<impl Responder> {
+pub async fn get_posts_handler(
+    state: web::Data<AppState>,
+    query: web::Query<PostListQuery>,
+    tags: TagQuery,
+    req: Option<ReqData<Claim>>,
+) -> Result<impl Responder, AppError> {
     let pool = &state.pool;
+    let page = query.page()?;
+    let filter = query.filter(&tags)?;
+    let user_id = claim_user_id(req)?;
+
+    let mut posts = get_posts(pool, &user_id, &filter, &page).await?;
+    let next_cursor = page.finish(&mut posts);
 
-    match get_posts(pool).await {
-        Ok(posts) => Ok(HttpResponse::Ok().json(json!({
-            "stataus": "success",
-            "result": posts.len(),
-            "posts": posts
-        }))),
-        Err(e) => Err(error::ErrorInternalServerError(json!({
-            "status": "fail",
-            "message": format!("Failed to retrieve posts: {}", e)
-        }))),
+    Ok(HttpResponse::Ok().json(json!({
+        "status": "success",
+        "result": posts.len(),
+        "posts": posts,
+        "next_cursor": next_cursor,
+        "has_more": next_cursor.is_some()
+    })))
+}
+
+// Full-text search over titles and contents, paginated like the listing
+#[get("/posts/search")]
+pub async fn search_posts_handler(
+    state: web::Data<AppState>,
+    query: web::Query<PostSearchQuery>,
+    tags: TagQuery,
+    req: Option<ReqData<Claim>>,
+) -> Result<impl Responder, AppError> {
+    let pool = &state.pool;
+    let terms = query.terms()?;
+    let page = query.page()?;
+    let filter = query.filter(&tags)?;
+    let user_id = claim_user_id(req)?;
+
+    let mut posts = search_posts(pool, &user_id, &terms, &filter, &page).await?;
+    let next_cursor = page.finish(&mut posts);
+
+    Ok(HttpResponse::Ok().json(json!({
+        "status": "success",
+        "result": posts.len(),
+        "posts": posts,
+        "next_cursor": next_cursor,
+        "has_more": next_cursor.is_some()
+    })))
+}
+
+// Retrieve a single post, optionally with its author embedded
+#[get("/posts/{id}")]
+pub async fn get_post_handler(
+    state: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    query: web::Query<PostQuery>,
+    http_req: HttpRequest,
+    req: Option<ReqData<Claim>>,
+) -> Result<impl Responder, AppError> {
+    let id = path.into_inner();
+    let user_id = claim_user_id(req)?;
+    let not_found = || AppError::NotFound("Post with given id not found!".to_string());
+
+    single_post_response(&state, &id, &user_id, &query, &http_req, not_found).await
+}
+
+// Retrieve a single post by its slug. Slugs the post had before a title change answer with a
+// permanent redirect to the current one.
+#[get("/posts/by-slug/{slug}")]
+pub async fn get_post_by_slug_handler(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<PostQuery>,
+    http_req: HttpRequest,
+    req: Option<ReqData<Claim>>,
+) -> Result<impl Responder, AppError> {
+    let slug = path.into_inner();
+    let user_id = claim_user_id(req)?;
+    let not_found = || AppError::NotFound("Post with given slug not found!".to_string());
+
+    let (id, current) = find_post_slug(&state.pool, &slug)
+        .await?
+        .ok_or_else(not_found)?;
+    if current == slug {
+        return single_post_response(&state, &id, &user_id, &query, &http_req, not_found).await;
+    }
+
+    // Redirect only to posts the caller may read, so old slugs reveal nothing
+    if get_post(&state.pool, &id, &user_id).await?.is_none() {
+        return Err(not_found());
+    }
+    let mut location = format!("/api/posts/by-slug/{}", current);
+    if !http_req.query_string().is_empty() {
+        location = format!("{}?{}", location, http_req.query_string());
+    }
+    Ok(HttpResponse::MovedPermanently()
+        .insert_header((LOCATION, location))
+        .finish())
+}
+
+// A post visible to the caller, optionally with its author embedded
+async fn single_post_response(
+    state: &AppState,
+    id: &Uuid,
+    user_id: &Uuid,
+    query: &PostQuery,
+    http_req: &HttpRequest,
+    not_found: impl Fn() -> AppError,
+) -> Result<HttpResponse, AppError> {
+    let pool = &state.pool;
+
+    // The embedded author changes without the post's version, so only the plain post is tagged
+    if query.expand_author()? {
+        let post = get_post_with_author(pool, id, user_id)
+            .await?
+            .ok_or_else(not_found)?;
+        return Ok(HttpResponse::Ok().json(json!({
+            "status": "success",
+            "post": post
+        })));
+    }
+
+    let post = get_post(pool, id, user_id).await?.ok_or_else(not_found)?;
+    if not_modified(http_req, &post) {
+        let mut builder = HttpResponse::NotModified();
+        tag_post(&mut builder, &post);
+        return Ok(builder.finish());
     }
+
+    Ok(post_response(HttpResponse::Ok(), post))
+}
+
+// Single post envelope tagged with the post's ETag
+pub fn post_response(mut builder: HttpResponseBuilder, post: Post) -> HttpResponse {
+    tag_post(&mut builder, &post);
+    builder.json(json!({
+        "status": "success",
+        "post": post
+    }))
 }
 
 // Create post and persist on the db
@@ -34,48 +174,34 @@ pub async fn create_post_handler(
     state: web::Data<AppState>,
     body: web::Json<NewPost>,
     req: Option<ReqData<Claim>>,
-) -> actix_web::Result<impl Responder> {
+    permissions: Permissions,
+) -> Result<impl Responder, AppError> {
     let pool = &state.pool;
+    permissions.require(POSTS_CREATE)?;
+    body.validate()?;
 
     let id = Uuid::new_v4();
-    let user_id = if let Some(token) = req {
-        parse_uuid(&token.sub)?
-    } else {
-        return Err(error::ErrorNotFound("No claims found in the request data"));
-    };
+    let user_id = claim_user_id(req)?;
+    let (status, published_at) = body.publication();
     let created_at = Utc::now();
     let updated_at = Utc::now();
 
-    match create_post(
+    let post = create_post(
         pool,
         &id,
         &user_id,
         &body.title,
         &body.content,
+        status,
+        published_at,
         &created_at,
         &updated_at,
+        &body.tags(),
     )
     .await
-    {
-        Ok(post) => Ok(HttpResponse::Created().json(json!({
-            "status": "success",
-            "post": post
-        }))),
-        Err(e) => {
-            if let Some(pg_error) = e.as_database_error() {
-                if pg_error.is_unique_violation() {
-                    return Err(error::ErrorConflict(json!({
-                        "status": "fail",
-                        "message": "Post with given title already exists!"
-                    })));
-                }
-            }
-            Err(error::ErrorInternalServerError(json!({
-                "status": "fail",
-                "message": format!("Error from database: {}", e)
-            })))
-        }
-    }
+    .map_err(|e| AppError::conflict_on_unique(e, TITLE_TAKEN))?;
+
+    Ok(post_response(HttpResponse::Created(), post))
 }
 
 // Update post with a given id
@@ -84,27 +210,119 @@ pub async fn edit_post_handler(
     state: web::Data<AppState>,
     path: web::Path<Uuid>,
     body: web::Json<UpdatePost>,
-) -> actix_web::Result<impl Responder> {
+    http_req: HttpRequest,
+    req: Option<ReqData<Claim>>,
+    permissions: Permissions,
+) -> Result<impl Responder, AppError> {
     let pool = &state.pool;
     let id = path.into_inner();
-    match update_post(pool, body.title.as_deref(), body.content.as_deref(), &id).await {
-        Ok(post) => Ok(HttpResponse::Ok().json(json!({
-            "status": "success",
-            "post": post
-        }))),
-        Err(e) => match e {
-            sqlx::error::Error::RowNotFound => {
-                return Err(error::ErrorNotFound(json!({
-                    "status": "fail",
-                    "message": "Post with given id not found!"
-                })))
-            }
-            _ => Err(error::ErrorInternalServerError(json!({
-                "status": "fail",
-                "message": format!("Error from database: {}", e)
-            }))),
-        },
-    }
+    let any = permissions.scope(POSTS_UPDATE_OWN, POSTS_UPDATE_ANY)?;
+    body.validate()?;
+    let expected = expected_versions(&http_req)?;
+    let user_id = claim_user_id(req)?;
+
+    let post = update_post(
+        pool,
+        body.title.as_deref(),
+        body.content.as_deref(),
+        body.tags().as_deref(),
+        &id,
+        &user_id,
+        any,
+        expected.as_deref(),
+    )
+    .await?;
+
+    Ok(post_response(HttpResponse::Ok(), post))
+}
+
+// Publish a post now, or schedule it when given a future published_at
+#[post("/posts/{id}/publish")]
+pub async fn publish_post_handler(
+    state: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    body: Option<web::Json<PublishPost>>,
+    req: Option<ReqData<Claim>>,
+    permissions: Permissions,
+) -> Result<impl Responder, AppError> {
+    let body = body.map(web::Json::into_inner).unwrap_or_default();
+    let (status, published_at) = body.publication();
+
+    change_post_status(
+        &state,
+        path.into_inner(),
+        req,
+        &permissions,
+        &[
+            PostStatus::Draft,
+            PostStatus::Scheduled,
+            PostStatus::Archived,
+        ],
+        status,
+        Some(published_at),
+    )
+    .await
+}
+
+// Turn a published or scheduled post back into a draft
+#[post("/posts/{id}/unpublish")]
+pub async fn unpublish_post_handler(
+    state: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    req: Option<ReqData<Claim>>,
+    permissions: Permissions,
+) -> Result<impl Responder, AppError> {
+    change_post_status(
+        &state,
+        path.into_inner(),
+        req,
+        &permissions,
+        &[PostStatus::Published, PostStatus::Scheduled],
+        PostStatus::Draft,
+        None,
+    )
+    .await
+}
+
+// Hide a post from everyone but its author without deleting it
+#[post("/posts/{id}/archive")]
+pub async fn archive_post_handler(
+    state: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    req: Option<ReqData<Claim>>,
+    permissions: Permissions,
+) -> Result<impl Responder, AppError> {
+    change_post_status(
+        &state,
+        path.into_inner(),
+        req,
+        &permissions,
+        &[
+            PostStatus::Draft,
+            PostStatus::Published,
+            PostStatus::Scheduled,
+        ],
+        PostStatus::Archived,
+        None,
+    )
+    .await
+}
+
+async fn change_post_status(
+    state: &AppState,
+    id: Uuid,
+    req: Option<ReqData<Claim>>,
+    permissions: &Permissions,
+    from: &[PostStatus],
+    to: PostStatus,
+    published_at: Option<DateTime<Utc>>,
+) -> Result<HttpResponse, AppError> {
+    let any = permissions.scope(POSTS_UPDATE_OWN, POSTS_UPDATE_ANY)?;
+    let user_id = claim_user_id(req)?;
+
+    let post = set_post_status(&state.pool, &id, &user_id, any, from, to, published_at).await?;
+
+    Ok(post_response(HttpResponse::Ok(), post))
 }
 
 //Delete post with a given id
@@ -112,23 +330,17 @@ pub async fn edit_post_handler(
 pub async fn delete_post_handler(
     state: web::Data<AppState>,
     path: web::Path<Uuid>,
-) -> actix_web::Result<impl Responder> {
+    http_req: HttpRequest,
+    req: Option<ReqData<Claim>>,
+    permissions: Permissions,
+) -> Result<impl Responder, AppError> {
     let pool = &state.pool;
     let id = path.into_inner();
+    let any = permissions.scope(POSTS_DELETE_OWN, POSTS_DELETE_ANY)?;
+    let user_id = claim_user_id(req)?;
+    let expected = expected_versions(&http_req)?;
 
-    match delete_post(pool, &id).await {
-        Ok(_) => Ok(HttpResponse::Ok().finish()),
-        Err(e) => match e {
-            sqlx::error::Error::RowNotFound => {
-                return Err(error::ErrorNotFound(json!({
-                    "status": "fail",
-                    "message": "Post with given id not found!"
-                })))
-            }
-            _ => Err(error::ErrorInternalServerError(json!({
-                "status": "fail",
-                "message": format!("Error from database: {}", e)
-            }))),
-        },
-    }
+    delete_post(pool, &id, &user_id, any, expected.as_deref()).await?;
+
+    Ok(HttpResponse::Ok().finish())
 }
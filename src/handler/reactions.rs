@@ -0,0 +1,66 @@
+use actix_web::web::ReqData;
+use actix_web::{delete, put, web, HttpResponse, Responder};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::error::AppError;
+use crate::model::{Claim, Post, ReactionKind};
+use crate::queries::{add_reaction, get_post, remove_reaction};
+use crate::utils::claim_user_id;
+use crate::AppState;
+
+// Anyone who can read a post may react to it. Both endpoints are idempotent and answer with
+// the post's reactions after the change.
+
+// React to a post with a kind
+#[put("/posts/{id}/reactions/{kind}")]
+pub async fn add_reaction_handler(
+    state: web::Data<AppState>,
+    path: web::Path<(Uuid, ReactionKind)>,
+    req: Option<ReqData<Claim>>,
+) -> Result<impl Responder, AppError> {
+    let pool = &state.pool;
+    let (id, kind) = path.into_inner();
+    let user_id = claim_user_id(req)?;
+    visible_post(&state, &id, &user_id).await?;
+
+    add_reaction(pool, &id, &user_id, kind).await?;
+
+    Ok(reactions_response(
+        visible_post(&state, &id, &user_id).await?,
+    ))
+}
+
+// Take back a reaction of a kind
+#[delete("/posts/{id}/reactions/{kind}")]
+pub async fn remove_reaction_handler(
+    state: web::Data<AppState>,
+    path: web::Path<(Uuid, ReactionKind)>,
+    req: Option<ReqData<Claim>>,
+) -> Result<impl Responder, AppError> {
+    let pool = &state.pool;
+    let (id, kind) = path.into_inner();
+    let user_id = claim_user_id(req)?;
+    visible_post(&state, &id, &user_id).await?;
+
+    remove_reaction(pool, &id, &user_id, kind).await?;
+
+    Ok(reactions_response(
+        visible_post(&state, &id, &user_id).await?,
+    ))
+}
+
+async fn visible_post(state: &AppState, id: &Uuid, user_id: &Uuid) -> Result<Post, AppError> {
+    get_post(&state.pool, id, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Post with given id not found!".to_string()))
+}
+
+fn reactions_response(post: Post) -> HttpResponse {
+    HttpResponse::Ok().json(json!({
+        "status": "success",
+        "reactions": post.reactions,
+        "reaction_count": post.reaction_count,
+        "reacted": post.reacted
+    }))
+}
@@ -0,0 +1,141 @@
+use actix_web::web::ReqData;
+use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::diff::line_diff;
+use crate::error::AppError;
+use crate::etag::expected_versions;
+use crate::handler::posts::post_response;
+use crate::model::{Claim, RevisionDiffQuery};
+use crate::queries::{
+    check_post_editable, get_editable_post_text, get_post_revision, get_post_revisions, update_post,
+};
+use crate::rbac::{Permissions, POSTS_UPDATE_ANY, POSTS_UPDATE_OWN};
+use crate::utils::claim_user_id;
+use crate::AppState;
+
+// Revision history is available to whoever may edit the post
+
+// List the revisions of a post, newest first
+#[get("/posts/{id}/revisions")]
+pub async fn get_revisions_handler(
+    state: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    req: Option<ReqData<Claim>>,
+    permissions: Permissions,
+) -> Result<impl Responder, AppError> {
+    let pool = &state.pool;
+    let id = path.into_inner();
+    let any = permissions.scope(POSTS_UPDATE_OWN, POSTS_UPDATE_ANY)?;
+    let user_id = claim_user_id(req)?;
+    check_post_editable(pool, &id, &user_id, any).await?;
+
+    let revisions = get_post_revisions(pool, &id).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "status": "success",
+        "result": revisions.len(),
+        "revisions": revisions
+    })))
+}
+
+// Line-level diff between two revisions, or between a revision and the current post
+#[get("/posts/{id}/revisions/diff")]
+pub async fn diff_revisions_handler(
+    state: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    query: web::Query<RevisionDiffQuery>,
+    req: Option<ReqData<Claim>>,
+    permissions: Permissions,
+) -> Result<impl Responder, AppError> {
+    let pool = &state.pool;
+    let id = path.into_inner();
+    let any = permissions.scope(POSTS_UPDATE_OWN, POSTS_UPDATE_ANY)?;
+    let user_id = claim_user_id(req)?;
+    check_post_editable(pool, &id, &user_id, any).await?;
+
+    let from = get_post_revision(pool, &id, query.from)
+        .await?
+        .ok_or_else(|| revision_not_found(query.from))?;
+    let (to_title, to_content) = match query.to {
+        Some(to) => {
+            let to = get_post_revision(pool, &id, to)
+                .await?
+                .ok_or_else(|| revision_not_found(to))?;
+            (to.title, to.content)
+        }
+        None => get_editable_post_text(pool, &id, &user_id, any)
+            .await?
+            .ok_or_else(|| AppError::NotFound("Post with given id not found!".to_string()))?,
+    };
+
+    Ok(HttpResponse::Ok().json(json!({
+        "status": "success",
+        "from": from.revision,
+        "to": query.to,
+        "title": line_diff(&from.title, &to_title),
+        "content": line_diff(&from.content, &to_content)
+    })))
+}
+
+// Fetch a single revision with its content
+#[get("/posts/{id}/revisions/{revision}")]
+pub async fn get_revision_handler(
+    state: web::Data<AppState>,
+    path: web::Path<(Uuid, i32)>,
+    req: Option<ReqData<Claim>>,
+    permissions: Permissions,
+) -> Result<impl Responder, AppError> {
+    let pool = &state.pool;
+    let (id, revision) = path.into_inner();
+    let any = permissions.scope(POSTS_UPDATE_OWN, POSTS_UPDATE_ANY)?;
+    let user_id = claim_user_id(req)?;
+    check_post_editable(pool, &id, &user_id, any).await?;
+
+    let revision = get_post_revision(pool, &id, revision)
+        .await?
+        .ok_or_else(|| revision_not_found(revision))?;
+    Ok(HttpResponse::Ok().json(json!({
+        "status": "success",
+        "revision": revision
+    })))
+}
+
+// Bring back the title and content of a revision. This is an edit like any other, so the
+// state being replaced becomes a new revision.
+#[post("/posts/{id}/revisions/{revision}/restore")]
+pub async fn restore_revision_handler(
+    state: web::Data<AppState>,
+    path: web::Path<(Uuid, i32)>,
+    http_req: HttpRequest,
+    req: Option<ReqData<Claim>>,
+    permissions: Permissions,
+) -> Result<impl Responder, AppError> {
+    let pool = &state.pool;
+    let (id, revision) = path.into_inner();
+    let any = permissions.scope(POSTS_UPDATE_OWN, POSTS_UPDATE_ANY)?;
+    let user_id = claim_user_id(req)?;
+    let expected = expected_versions(&http_req)?;
+    check_post_editable(pool, &id, &user_id, any).await?;
+
+    let revision = get_post_revision(pool, &id, revision)
+        .await?
+        .ok_or_else(|| revision_not_found(revision))?;
+    let post = update_post(
+        pool,
+        Some(&revision.title),
+        Some(&revision.content),
+        None,
+        &id,
+        &user_id,
+        any,
+        expected.as_deref(),
+    )
+    .await?;
+
+    Ok(post_response(HttpResponse::Ok(), post))
+}
+
+fn revision_not_found(revision: i32) -> AppError {
+    AppError::NotFound(format!("Revision {} of this post not found!", revision))
+}
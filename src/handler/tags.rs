@@ -0,0 +1,25 @@
+use actix_web::web::ReqData;
+use actix_web::{get, web, HttpResponse, Responder};
+use serde_json::json;
+
+use crate::error::AppError;
+use crate::model::Claim;
+use crate::queries::get_tags;
+use crate::utils::claim_user_id;
+use crate::AppState;
+
+// List the tags in use with how many posts visible to the caller carry each
+#[get("/tags")]
+pub async fn get_tags_handler(
+    state: web::Data<AppState>,
+    req: Option<ReqData<Claim>>,
+) -> Result<impl Responder, AppError> {
+    let user_id = claim_user_id(req)?;
+
+    let tags = get_tags(&state.pool, &user_id).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "status": "success",
+        "result": tags.len(),
+        "tags": tags
+    })))
+}
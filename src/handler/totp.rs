@@ -0,0 +1,139 @@
+use actix_web::web::ReqData;
+use actix_web::{delete, post, web, HttpResponse, Responder};
+use chrono::Utc;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::error::AppError;
+use crate::mfa::{check_second_factor, MfaError};
+use crate::model::{Claim, TotpConfirmation, TotpDisable};
+use crate::queries::{
+    disable_totp, enable_totp, get_totp_state, get_user, replace_recovery_codes,
+    set_pending_totp_secret,
+};
+use crate::throttle::{check_login_allowed, record_login_failure, ACCOUNT_SCOPE};
+use crate::totp::{
+    base32_encode, generate_recovery_codes, generate_secret, normalize_recovery_code, otpauth_uri,
+    verify_code,
+};
+use crate::utils::{claim_user_id, hash_token, verify_hashed_password};
+use crate::AppState;
+
+// Start enrolling an authenticator app. Calling it again before confirming replaces the secret.
+#[post("/account/totp")]
+pub async fn enroll_totp_handler(
+    state: web::Data<AppState>,
+    req: Option<ReqData<Claim>>,
+) -> Result<impl Responder, AppError> {
+    let pool = &state.pool;
+    let user_id = claim_user_id(req)?;
+    let user = get_user(pool, &user_id).await?;
+
+    let secret = generate_secret();
+    if !set_pending_totp_secret(pool, &user_id, &secret).await? {
+        return Err(totp_enabled());
+    }
+
+    Ok(HttpResponse::Ok().json(json!({
+        "status": "success",
+        "message": "Add the secret to your authenticator app and confirm it with a first code",
+        "secret": base32_encode(&secret),
+        "otpauth_uri": otpauth_uri(&state.settings.auth.totp_issuer, &user.email, &secret)
+    })))
+}
+
+// Turn two-factor authentication on with the account password and a first code of the
+// enrolled app. The recovery codes are only ever shown in this response.
+#[post("/account/totp/confirm")]
+pub async fn confirm_totp_handler(
+    state: web::Data<AppState>,
+    body: web::Json<TotpConfirmation>,
+    req: Option<ReqData<Claim>>,
+) -> Result<impl Responder, AppError> {
+    let user_id = claim_user_id(req)?;
+    check_account_password(&state, &user_id, &body.password).await?;
+
+    let mut tx = state.pool.begin().await?;
+
+    let totp = get_totp_state(&mut tx, &user_id).await?;
+    if totp.enabled_at.is_some() {
+        return Err(totp_enabled());
+    }
+    let secret = totp.secret.ok_or_else(|| {
+        AppError::Conflict("Start enrolling an authenticator app first!".to_string())
+    })?;
+    let step = verify_code(&secret, &body.code, Utc::now().timestamp(), None)
+        .ok_or_else(|| AppError::validation("code", "is invalid or has expired"))?;
+
+    let recovery_codes = generate_recovery_codes();
+    let code_hashes: Vec<String> = recovery_codes
+        .iter()
+        .map(|code| hash_token(&normalize_recovery_code(code)))
+        .collect();
+    if !enable_totp(&mut tx, &user_id, step).await? {
+        return Err(totp_enabled());
+    }
+    replace_recovery_codes(&mut tx, &user_id, &code_hashes).await?;
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "status": "success",
+        "message": "Two-factor authentication enabled. Store the recovery codes somewhere safe.",
+        "recovery_codes": recovery_codes
+    })))
+}
+
+// Turn two-factor authentication off, which takes the account password and a current code.
+// Wrong codes count against the account like wrong passwords.
+#[delete("/account/totp")]
+pub async fn disable_totp_handler(
+    state: web::Data<AppState>,
+    body: web::Json<TotpDisable>,
+    req: Option<ReqData<Claim>>,
+) -> Result<impl Responder, AppError> {
+    let user_id = claim_user_id(req)?;
+    check_account_password(&state, &user_id, &body.password).await?;
+
+    let mut tx = state.pool.begin().await?;
+    // An enrollment that was never confirmed can be dropped without a code
+    let enabled = get_totp_state(&mut tx, &user_id)
+        .await?
+        .enabled_at
+        .is_some();
+    if enabled && !check_second_factor(&mut tx, &user_id, &body.code).await? {
+        tx.rollback().await?;
+        record_login_failure(&state, ACCOUNT_SCOPE, &user_id.to_string()).await?;
+        return Err(MfaError::WrongCode.into());
+    }
+    disable_totp(&mut tx, &user_id).await?;
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "status": "success",
+        "message": "Two-factor authentication disabled"
+    })))
+}
+
+// Changing two-factor authentication takes the account password. Wrong ones count against the
+// account like failed logins, so a stolen access token can't be used to guess it.
+async fn check_account_password(
+    state: &AppState,
+    user_id: &Uuid,
+    password: &str,
+) -> Result<(), AppError> {
+    let account = user_id.to_string();
+    check_login_allowed(state, ACCOUNT_SCOPE, &account).await?;
+
+    let user = get_user(&state.pool, user_id).await?;
+    if let Err(e) = verify_hashed_password(password, &user.password) {
+        if e == argon2::password_hash::Error::Password {
+            record_login_failure(state, ACCOUNT_SCOPE, &account).await?;
+        }
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+fn totp_enabled() -> AppError {
+    AppError::Conflict("Two-factor authentication is enabled already!".to_string())
+}
@@ -0,0 +1,51 @@
+use actix_web::web::ReqData;
+use actix_web::{get, post, web, HttpResponse, Responder};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::error::AppError;
+use crate::handler::posts::post_response;
+use crate::model::Claim;
+use crate::queries::{get_trashed_posts, restore_post};
+use crate::rbac::{Permissions, POSTS_DELETE_ANY, POSTS_DELETE_OWN};
+use crate::utils::claim_user_id;
+use crate::AppState;
+
+// Deleted posts wait in their author's trash until they are restored or purged
+
+// List the posts in the caller's trash
+#[get("/posts/trash")]
+pub async fn get_trash_handler(
+    state: web::Data<AppState>,
+    req: Option<ReqData<Claim>>,
+) -> Result<impl Responder, AppError> {
+    let user_id = claim_user_id(req)?;
+
+    let posts = get_trashed_posts(
+        &state.pool,
+        &user_id,
+        state.settings.posts.trash_retention_days,
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "status": "success",
+        "result": posts.len(),
+        "posts": posts
+    })))
+}
+
+// Take a post out of the trash, as whoever could have deleted it
+#[post("/posts/{id}/restore")]
+pub async fn restore_post_handler(
+    state: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    req: Option<ReqData<Claim>>,
+    permissions: Permissions,
+) -> Result<impl Responder, AppError> {
+    let id = path.into_inner();
+    let any = permissions.scope(POSTS_DELETE_OWN, POSTS_DELETE_ANY)?;
+    let user_id = claim_user_id(req)?;
+
+    let post = restore_post(&state.pool, &id, &user_id, any).await?;
+    Ok(post_response(HttpResponse::Ok(), post))
+}
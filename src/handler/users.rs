@@ -0,0 +1,36 @@
+use actix_web::{post, web, HttpResponse, Responder};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::error::AppError;
+use crate::queries::get_user;
+use crate::rbac::{Permissions, USERS_MANAGE};
+use crate::throttle::reset_account_throttle;
+use crate::AppState;
+
+// Lift the lock failed logins put on an account and forget its failures
+#[post("/users/{id}/unlock")]
+pub async fn unlock_user_handler(
+    state: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    permissions: Permissions,
+) -> Result<impl Responder, AppError> {
+    permissions.require(USERS_MANAGE)?;
+    let id = path.into_inner();
+
+    match get_user(&state.pool, &id).await {
+        Ok(_) => {}
+        Err(sqlx::Error::RowNotFound) => {
+            return Err(AppError::NotFound(
+                "User with given id not found!".to_string(),
+            ))
+        }
+        Err(e) => return Err(e.into()),
+    }
+    reset_account_throttle(&state, &id).await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "status": "success",
+        "message": "Account unlocked"
+    })))
+}
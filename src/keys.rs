@@ -0,0 +1,665 @@
+use std::{
+    fmt, fs,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, PoisonError, RwLock},
+};
+
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine,
+};
+use chrono::{DateTime, Duration, Utc};
+use ed25519_dalek::pkcs8::{spki::der::pem::LineEnding, DecodePrivateKey, EncodePrivateKey};
+use jsonwebtoken::{
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, EllipticCurveKeyParameters,
+        EllipticCurveKeyType, Jwk, JwkSet, KeyAlgorithm, OctetKeyPairParameters, OctetKeyPairType,
+        PublicKeyUse, RSAKeyParameters, RSAKeyType,
+    },
+    Algorithm, DecodingKey, EncodingKey,
+};
+use p256::elliptic_curve::sec1::ToEncodedPoint;
+use rand_core::{OsRng, RngCore};
+use rsa::{pkcs1::DecodeRsaPrivateKey, traits::PublicKeyParts, RsaPrivateKey};
+use serde::{Deserialize, Serialize};
+
+use crate::settings::JwtSettings;
+
+// Algorithms access tokens can be signed with
+pub const SUPPORTED_ALGORITHMS: [Algorithm; 4] = [
+    Algorithm::HS256,
+    Algorithm::RS256,
+    Algorithm::ES256,
+    Algorithm::EdDSA,
+];
+
+// Key used to sign and verify tokens, identified by its kid
+pub struct SigningKey {
+    pub kid: String,
+    pub algorithm: Algorithm,
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    jwk: Option<Jwk>,
+}
+
+impl SigningKey {
+    // HMAC key shared between issuer and verifiers, never published
+    pub fn from_secret(kid: &str, secret: &[u8]) -> SigningKey {
+        SigningKey {
+            kid: kid.to_string(),
+            algorithm: Algorithm::HS256,
+            encoding: EncodingKey::from_secret(secret),
+            decoding: DecodingKey::from_secret(secret),
+            jwk: None,
+        }
+    }
+
+    // Asymmetric key pair from a PEM encoded private key (PKCS#8, or PKCS#1/SEC1 for RSA/ECDSA)
+    pub fn from_private_key_pem(
+        kid: &str,
+        algorithm: Algorithm,
+        pem: &str,
+    ) -> Result<SigningKey, KeyError> {
+        let (encoding, params) = match algorithm {
+            Algorithm::RS256 => {
+                let key = RsaPrivateKey::from_pkcs8_pem(pem)
+                    .or_else(|_| RsaPrivateKey::from_pkcs1_pem(pem))
+                    .map_err(|e| KeyError(format!("invalid RSA private key: {}", e)))?;
+                let encoding = EncodingKey::from_rsa_pem(pem.as_bytes())
+                    .map_err(|e| KeyError(format!("invalid RSA private key: {}", e)))?;
+                let params = AlgorithmParameters::RSA(RSAKeyParameters {
+                    key_type: RSAKeyType::RSA,
+                    n: URL_SAFE_NO_PAD.encode(key.n().to_bytes_be()),
+                    e: URL_SAFE_NO_PAD.encode(key.e().to_bytes_be()),
+                });
+                (encoding, params)
+            }
+            Algorithm::ES256 => {
+                let key = p256::SecretKey::from_pkcs8_pem(pem)
+                    .or_else(|_| p256::SecretKey::from_sec1_pem(pem))
+                    .map_err(|e| KeyError(format!("invalid P-256 private key: {}", e)))?;
+                let der = key
+                    .to_pkcs8_der()
+                    .map_err(|e| KeyError(format!("invalid P-256 private key: {}", e)))?;
+                let point = key.public_key().to_encoded_point(false);
+                let (Some(x), Some(y)) = (point.x(), point.y()) else {
+                    return Err(KeyError("invalid P-256 public key".to_string()));
+                };
+                let params = AlgorithmParameters::EllipticCurve(EllipticCurveKeyParameters {
+                    key_type: EllipticCurveKeyType::EC,
+                    curve: EllipticCurve::P256,
+                    x: URL_SAFE_NO_PAD.encode(x),
+                    y: URL_SAFE_NO_PAD.encode(y),
+                });
+                (EncodingKey::from_ec_der(der.as_bytes()), params)
+            }
+            Algorithm::EdDSA => {
+                let key = ed25519_dalek::SigningKey::from_pkcs8_pem(pem)
+                    .map_err(|e| KeyError(format!("invalid Ed25519 private key: {}", e)))?;
+                let der = key
+                    .to_pkcs8_der()
+                    .map_err(|e| KeyError(format!("invalid Ed25519 private key: {}", e)))?;
+                let params = AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                    key_type: OctetKeyPairType::OctetKeyPair,
+                    curve: EllipticCurve::Ed25519,
+                    x: URL_SAFE_NO_PAD.encode(key.verifying_key().as_bytes()),
+                });
+                (EncodingKey::from_ed_der(der.as_bytes()), params)
+            }
+            other => {
+                return Err(KeyError(format!(
+                    "{:?} is not an asymmetric signing algorithm",
+                    other
+                )))
+            }
+        };
+
+        let jwk = Jwk {
+            common: CommonParameters {
+                public_key_use: Some(PublicKeyUse::Signature),
+                key_algorithm: Some(key_algorithm(algorithm)),
+                key_id: Some(kid.to_string()),
+                ..Default::default()
+            },
+            algorithm: params,
+        };
+        let decoding = DecodingKey::from_jwk(&jwk)
+            .map_err(|e| KeyError(format!("invalid public key: {}", e)))?;
+
+        Ok(SigningKey {
+            kid: kid.to_string(),
+            algorithm,
+            encoding,
+            decoding,
+            jwk: Some(jwk),
+        })
+    }
+
+    pub fn encoding_key(&self) -> &EncodingKey {
+        &self.encoding
+    }
+
+    pub fn decoding_key(&self) -> &DecodingKey {
+        &self.decoding
+    }
+
+    // Public part of the key, None for shared secrets
+    pub fn jwk(&self) -> Option<&Jwk> {
+        self.jwk.as_ref()
+    }
+}
+
+// Keys the application signs and verifies tokens with
+pub struct JwtKeys {
+    ring_dir: Option<KeyRingDir>,
+    keys: RwLock<Vec<RingKey>>,
+}
+
+// Loaded key with the time until which it may still verify tokens
+struct RingKey {
+    key: Arc<SigningKey>,
+    current: bool,
+    valid_until: Option<DateTime<Utc>>,
+}
+
+impl RingKey {
+    fn usable(&self, now: DateTime<Utc>) -> bool {
+        self.valid_until.is_none_or(|until| until > now)
+    }
+}
+
+impl JwtKeys {
+    pub fn from_settings(jwt: &JwtSettings) -> Result<JwtKeys, KeyError> {
+        if let Some(dir) = &jwt.key_ring_dir {
+            let ring_dir = KeyRingDir::new(dir, jwt.key_grace_period_days);
+            if ring_dir.records()?.is_empty() {
+                ring_dir.rotate(jwt.algorithm)?;
+            }
+
+            let keys = JwtKeys {
+                keys: RwLock::new(ring_dir.load()?),
+                ring_dir: Some(ring_dir),
+            };
+            return Ok(keys);
+        }
+
+        let current = match (jwt.algorithm, &jwt.private_key_file) {
+            (Algorithm::HS256, _) => SigningKey::from_secret(&jwt.key_id, jwt.secret.as_bytes()),
+            (algorithm, Some(path)) => {
+                let pem = fs::read_to_string(path)
+                    .map_err(|e| KeyError(format!("failed to read {}: {}", path.display(), e)))?;
+                SigningKey::from_private_key_pem(&jwt.key_id, algorithm, &pem)
+                    .map_err(|e| KeyError(format!("{}: {}", path.display(), e)))?
+            }
+            (algorithm, None) => {
+                return Err(KeyError(format!(
+                    "{:?} requires jwt.private_key_file",
+                    algorithm
+                )))
+            }
+        };
+
+        Ok(JwtKeys {
+            ring_dir: None,
+            keys: RwLock::new(vec![RingKey {
+                key: Arc::new(current),
+                current: true,
+                valid_until: None,
+            }]),
+        })
+    }
+
+    // Pick up keys rotated by another process, a no-op without a key ring
+    pub fn reload(&self) -> Result<(), KeyError> {
+        if let Some(ring_dir) = &self.ring_dir {
+            let keys = ring_dir.load()?;
+            *self.keys.write().unwrap_or_else(PoisonError::into_inner) = keys;
+        }
+        Ok(())
+    }
+
+    // Key new tokens are signed with
+    pub fn signing_key(&self) -> Arc<SigningKey> {
+        let keys = self.keys.read().unwrap_or_else(PoisonError::into_inner);
+        keys.iter()
+            .find(|k| k.current)
+            .map(|k| k.key.clone())
+            .expect("key ring always holds a current key")
+    }
+
+    // Key a token is verified with, looked up by the kid in its header.
+    // Tokens without a kid predate key rotation and are checked against the current key.
+    pub fn verification_key(&self, kid: Option<&str>) -> Option<Arc<SigningKey>> {
+        let now = Utc::now();
+        let keys = self.keys.read().unwrap_or_else(PoisonError::into_inner);
+        keys.iter()
+            .filter(|k| k.usable(now))
+            .find(|k| match kid {
+                Some(kid) => k.key.kid == kid,
+                None => k.current,
+            })
+            .map(|k| k.key.clone())
+    }
+
+    // Public keys published on the JWKS endpoint
+    pub fn jwk_set(&self) -> JwkSet {
+        let now = Utc::now();
+        let keys = self.keys.read().unwrap_or_else(PoisonError::into_inner);
+        JwkSet {
+            keys: keys
+                .iter()
+                .filter(|k| k.usable(now))
+                .filter_map(|k| k.key.jwk().cloned())
+                .collect(),
+        }
+    }
+}
+
+// Entry of the key ring manifest
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyRecord {
+    pub kid: String,
+    pub algorithm: Algorithm,
+    pub file: String,
+    pub created_at: DateTime<Utc>,
+    pub retired_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyStatus {
+    Current,
+    Retired { valid_until: DateTime<Utc> },
+    Expired,
+}
+
+impl fmt::Display for KeyStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyStatus::Current => write!(f, "current"),
+            KeyStatus::Retired { valid_until } => {
+                write!(f, "retired, verifies until {}", valid_until.to_rfc3339())
+            }
+            KeyStatus::Expired => write!(f, "expired"),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Manifest {
+    keys: Vec<KeyRecord>,
+}
+
+// Directory holding the ring's private keys and its ring.json manifest.
+// The last record without retired_at is the current signing key.
+pub struct KeyRingDir {
+    dir: PathBuf,
+    grace_period: Duration,
+}
+
+impl KeyRingDir {
+    pub fn new(dir: &Path, grace_period_days: i64) -> KeyRingDir {
+        KeyRingDir {
+            dir: dir.to_path_buf(),
+            grace_period: Duration::days(grace_period_days),
+        }
+    }
+
+    pub fn records(&self) -> Result<Vec<KeyRecord>, KeyError> {
+        Ok(self.read_manifest()?.keys)
+    }
+
+    pub fn status(&self, record: &KeyRecord, now: DateTime<Utc>) -> KeyStatus {
+        match record.retired_at {
+            None => KeyStatus::Current,
+            Some(retired_at) if retired_at + self.grace_period > now => KeyStatus::Retired {
+                valid_until: retired_at + self.grace_period,
+            },
+            Some(_) => KeyStatus::Expired,
+        }
+    }
+
+    // Generate a new key, make it the signing key and retire the previous one
+    pub fn rotate(&self, algorithm: Algorithm) -> Result<KeyRecord, KeyError> {
+        let mut manifest = self.read_manifest()?;
+        let now = Utc::now();
+
+        let mut kid = format!(
+            "{}-{}",
+            format!("{:?}", algorithm).to_lowercase(),
+            now.format("%Y%m%dT%H%M%SZ")
+        );
+        let taken = |kid: &str| manifest.keys.iter().any(|k| k.kid == kid);
+        if taken(&kid) {
+            kid = (2..)
+                .map(|n| format!("{}-{}", kid, n))
+                .find(|candidate| !taken(candidate))
+                .expect("unbounded range yields a free kid");
+        }
+
+        let file = format!("{}.pem", kid);
+        fs::create_dir_all(&self.dir)
+            .map_err(|e| KeyError(format!("failed to create {}: {}", self.dir.display(), e)))?;
+        write_private(&self.dir.join(&file), &generate_private_key(algorithm)?)?;
+
+        for record in manifest.keys.iter_mut() {
+            record.retired_at.get_or_insert(now);
+        }
+        let record = KeyRecord {
+            kid,
+            algorithm,
+            file,
+            created_at: now,
+            retired_at: None,
+        };
+        manifest.keys.push(record.clone());
+        self.write_manifest(&manifest)?;
+
+        Ok(record)
+    }
+
+    // Forget keys past their grace period and delete their private key files
+    pub fn prune(&self) -> Result<Vec<KeyRecord>, KeyError> {
+        let mut manifest = self.read_manifest()?;
+        let now = Utc::now();
+
+        let (expired, kept): (Vec<_>, Vec<_>) = manifest
+            .keys
+            .into_iter()
+            .partition(|record| self.status(record, now) == KeyStatus::Expired);
+        manifest.keys = kept;
+        self.write_manifest(&manifest)?;
+
+        for record in &expired {
+            let path = self.dir.join(&record.file);
+            if let Err(e) = fs::remove_file(&path) {
+                log::warn!("failed to remove {}: {}", path.display(), e);
+            }
+        }
+
+        Ok(expired)
+    }
+
+    fn load(&self) -> Result<Vec<RingKey>, KeyError> {
+        let now = Utc::now();
+        let mut keys = Vec::new();
+
+        for record in self.records()? {
+            let valid_until = match self.status(&record, now) {
+                KeyStatus::Current => None,
+                KeyStatus::Retired { valid_until } => Some(valid_until),
+                KeyStatus::Expired => continue,
+            };
+
+            let path = self.dir.join(&record.file);
+            let contents = fs::read_to_string(&path)
+                .map_err(|e| KeyError(format!("failed to read {}: {}", path.display(), e)))?;
+            let key = match record.algorithm {
+                Algorithm::HS256 => {
+                    let secret = STANDARD.decode(contents.trim()).map_err(|e| {
+                        KeyError(format!("{}: invalid secret: {}", path.display(), e))
+                    })?;
+                    SigningKey::from_secret(&record.kid, &secret)
+                }
+                algorithm => SigningKey::from_private_key_pem(&record.kid, algorithm, &contents)
+                    .map_err(|e| KeyError(format!("{}: {}", path.display(), e)))?,
+            };
+
+            keys.push(RingKey {
+                key: Arc::new(key),
+                current: valid_until.is_none(),
+                valid_until,
+            });
+        }
+
+        if !keys.iter().any(|k| k.current) {
+            return Err(KeyError(format!(
+                "key ring {} has no current key",
+                self.dir.display()
+            )));
+        }
+
+        Ok(keys)
+    }
+
+    fn manifest_path(&self) -> PathBuf {
+        self.dir.join("ring.json")
+    }
+
+    fn read_manifest(&self) -> Result<Manifest, KeyError> {
+        let path = self.manifest_path();
+        if !path.exists() {
+            return Ok(Manifest::default());
+        }
+
+        let contents = fs::read_to_string(&path)
+            .map_err(|e| KeyError(format!("failed to read {}: {}", path.display(), e)))?;
+        serde_json::from_str(&contents)
+            .map_err(|e| KeyError(format!("failed to parse {}: {}", path.display(), e)))
+    }
+
+    // Replace the manifest atomically so running servers never read half of it
+    fn write_manifest(&self, manifest: &Manifest) -> Result<(), KeyError> {
+        let path = self.manifest_path();
+        let tmp = self.dir.join("ring.json.tmp");
+        let contents = serde_json::to_string_pretty(manifest)
+            .map_err(|e| KeyError(format!("failed to serialize key ring: {}", e)))?;
+
+        fs::write(&tmp, contents)
+            .and_then(|_| fs::rename(&tmp, &path))
+            .map_err(|e| KeyError(format!("failed to write {}: {}", path.display(), e)))
+    }
+}
+
+fn generate_private_key(algorithm: Algorithm) -> Result<String, KeyError> {
+    let pem = match algorithm {
+        Algorithm::HS256 => {
+            let mut secret = [0u8; 64];
+            OsRng.fill_bytes(&mut secret);
+            return Ok(STANDARD.encode(secret));
+        }
+        Algorithm::RS256 => RsaPrivateKey::new(&mut OsRng, 2048)
+            .map_err(|e| KeyError(format!("failed to generate RSA key: {}", e)))?
+            .to_pkcs8_pem(LineEnding::LF),
+        Algorithm::ES256 => p256::SecretKey::random(&mut OsRng).to_pkcs8_pem(LineEnding::LF),
+        Algorithm::EdDSA => {
+            ed25519_dalek::SigningKey::generate(&mut OsRng).to_pkcs8_pem(LineEnding::LF)
+        }
+        other => {
+            return Err(KeyError(format!(
+                "{:?} is not a supported signing algorithm",
+                other
+            )))
+        }
+    };
+
+    pem.map(|pem| pem.to_string())
+        .map_err(|e| KeyError(format!("failed to encode private key: {}", e)))
+}
+
+// Private keys are only readable by their owner
+fn write_private(path: &Path, contents: &str) -> Result<(), KeyError> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .map_err(|e| KeyError(format!("failed to write {}: {}", path.display(), e)))
+}
+
+#[derive(Debug)]
+pub struct KeyError(pub String);
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid signing key: {}", self.0)
+    }
+}
+
+impl std::error::Error for KeyError {}
+
+fn key_algorithm(algorithm: Algorithm) -> KeyAlgorithm {
+    match algorithm {
+        Algorithm::RS256 => KeyAlgorithm::RS256,
+        Algorithm::ES256 => KeyAlgorithm::ES256,
+        Algorithm::EdDSA => KeyAlgorithm::EdDSA,
+        _ => KeyAlgorithm::HS256,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Directory under the system temp dir, removed again when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let mut suffix = [0u8; 8];
+            OsRng.fill_bytes(&mut suffix);
+            let dir = std::env::temp_dir().join(format!(
+                "blog-keys-{}-{}",
+                name,
+                URL_SAFE_NO_PAD.encode(suffix)
+            ));
+            TempDir(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn keys(ring: KeyRingDir) -> JwtKeys {
+        JwtKeys {
+            keys: RwLock::new(ring.load().unwrap()),
+            ring_dir: Some(ring),
+        }
+    }
+
+    fn kids(keys: &JwtKeys) -> Vec<String> {
+        let keys = keys.keys.read().unwrap();
+        keys.iter().map(|k| k.key.kid.clone()).collect()
+    }
+
+    #[test]
+    fn rotate_makes_the_new_key_current_and_retires_the_old_one() {
+        let dir = TempDir::new("rotate");
+        let ring = KeyRingDir::new(&dir.0, 7);
+
+        let first = ring.rotate(Algorithm::HS256).unwrap();
+        let second = ring.rotate(Algorithm::ES256).unwrap();
+        assert_ne!(first.kid, second.kid);
+
+        let records = ring.records().unwrap();
+        assert_eq!(records.len(), 2);
+        let retired_at = records[0].retired_at.expect("first key retired");
+        assert_eq!(records[1].retired_at, None);
+
+        let now = Utc::now();
+        assert_eq!(ring.status(&records[1], now), KeyStatus::Current);
+        assert_eq!(
+            ring.status(&records[0], now),
+            KeyStatus::Retired {
+                valid_until: retired_at + Duration::days(7)
+            }
+        );
+
+        let keys = keys(ring);
+        assert_eq!(keys.signing_key().kid, second.kid);
+        assert_eq!(keys.verification_key(None).unwrap().kid, second.kid);
+        assert_eq!(
+            keys.verification_key(Some(&first.kid)).unwrap().kid,
+            first.kid
+        );
+
+        // Shared secrets are never published
+        let jwks = keys.jwk_set();
+        assert_eq!(jwks.keys.len(), 1);
+        assert_eq!(jwks.keys[0].common.key_id.as_deref(), Some(&*second.kid));
+    }
+
+    #[test]
+    fn rotate_within_the_same_second_picks_a_free_kid() {
+        let dir = TempDir::new("kids");
+        let ring = KeyRingDir::new(&dir.0, 7);
+
+        let kids: Vec<String> = (0..3)
+            .map(|_| ring.rotate(Algorithm::HS256).unwrap().kid)
+            .collect();
+        let mut unique = kids.clone();
+        unique.dedup();
+        assert_eq!(unique, kids);
+    }
+
+    #[test]
+    fn keys_past_their_grace_period_are_rejected_and_pruned() {
+        let dir = TempDir::new("prune");
+        let ring = KeyRingDir::new(&dir.0, 0);
+
+        let first = ring.rotate(Algorithm::HS256).unwrap();
+        let second = ring.rotate(Algorithm::HS256).unwrap();
+        let records = ring.records().unwrap();
+        assert_eq!(ring.status(&records[0], Utc::now()), KeyStatus::Expired);
+
+        let loaded = keys(KeyRingDir::new(&dir.0, 0));
+        assert!(loaded.verification_key(Some(&first.kid)).is_none());
+        assert_eq!(kids(&loaded), vec![second.kid.clone()]);
+
+        let pruned = ring.prune().unwrap();
+        assert_eq!(pruned.len(), 1);
+        assert_eq!(pruned[0].kid, first.kid);
+        assert!(!dir.0.join(&first.file).exists());
+        assert!(dir.0.join(&second.file).exists());
+
+        let records = ring.records().unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].kid, second.kid);
+    }
+
+    #[test]
+    fn unknown_kids_have_no_verification_key() {
+        let dir = TempDir::new("unknown");
+        let ring = KeyRingDir::new(&dir.0, 7);
+        ring.rotate(Algorithm::EdDSA).unwrap();
+
+        let keys = keys(ring);
+        assert!(keys.verification_key(Some("no-such-kid")).is_none());
+        assert!(keys.verification_key(Some("")).is_none());
+    }
+
+    #[test]
+    fn reload_keeps_kids_and_picks_up_rotations() {
+        let dir = TempDir::new("reload");
+        let ring = KeyRingDir::new(&dir.0, 7);
+        ring.rotate(Algorithm::HS256).unwrap();
+        ring.rotate(Algorithm::ES256).unwrap();
+
+        let keys = keys(KeyRingDir::new(&dir.0, 7));
+        let before = kids(&keys);
+        let signing = keys.signing_key().kid.clone();
+        keys.reload().unwrap();
+        assert_eq!(kids(&keys), before);
+        assert_eq!(keys.signing_key().kid, signing);
+
+        // Another process rotating the ring
+        let rotated = ring.rotate(Algorithm::ES256).unwrap();
+        keys.reload().unwrap();
+        assert_eq!(keys.signing_key().kid, rotated.kid);
+        assert!(keys.verification_key(Some(&signing)).is_some());
+    }
+
+    #[test]
+    fn load_fails_without_a_current_key() {
+        let dir = TempDir::new("empty");
+        let ring = KeyRingDir::new(&dir.0, 7);
+        assert!(ring.records().unwrap().is_empty());
+        assert!(ring.load().is_err());
+    }
+}
@@ -1,35 +1,119 @@
 use actix_web::{middleware::from_fn, web};
+use error::AppError;
 use handler::{
     auth::{
-        authenticate::{user_login_handler, user_logout_handler},
+        authenticate::{
+            mfa_login_handler, token_refresh_handler, user_login_handler, user_logout_handler,
+        },
+        jwks::jwks_handler,
+        password::{forgot_password_handler, reset_password_handler},
         register::user_registration_handler,
+        verification::{resend_verification_handler, verify_email_handler},
+    },
+    comments::{
+        create_comment_handler, delete_comment_handler, edit_comment_handler, get_comments_handler,
+        lock_comments_handler, unlock_comments_handler,
     },
     generic::health_checker_handler,
-    posts::{create_post_handler, delete_post_handler, edit_post_handler, get_posts_handler},
+    posts::{
+        archive_post_handler, create_post_handler, delete_post_handler, edit_post_handler,
+        get_post_by_slug_handler, get_post_handler, get_posts_handler, publish_post_handler,
+        search_posts_handler, unpublish_post_handler,
+    },
+    reactions::{add_reaction_handler, remove_reaction_handler},
+    revisions::{
+        diff_revisions_handler, get_revision_handler, get_revisions_handler,
+        restore_revision_handler,
+    },
+    tags::get_tags_handler,
+    totp::{confirm_totp_handler, disable_totp_handler, enroll_totp_handler},
+    trash::{get_trash_handler, restore_post_handler},
+    users::unlock_user_handler,
 };
 use middleware::jwt_middleware;
 
+mod comments;
+mod diff;
+mod email;
+mod error;
+mod etag;
 mod handler;
+mod keys;
+mod mfa;
 mod middleware;
 mod model;
+mod pagination;
+mod password_reset;
 mod queries;
+mod rbac;
+mod search;
+mod session;
+mod settings;
+mod slug;
+mod tags;
+mod throttle;
+mod totp;
 mod utils;
+mod verification;
+pub use email::{
+    email_sender, EmailError, EmailMessage, EmailSender, EmailTemplate, FileSender, LogSender,
+    MemorySender, SmtpSender, RESET_PASSWORD, VERIFY_EMAIL,
+};
+pub use keys::{JwtKeys, KeyError, KeyRecord, KeyRingDir, KeyStatus};
 pub use model::AppState;
+pub use queries::{publish_scheduled_posts, purge_trashed_posts};
+pub use settings::{CommandLine, DatabaseSettings, Settings, SettingsError};
 
 pub fn config(conf: &mut web::ServiceConfig) {
+    // Malformed bodies, paths and queries are answered with the AppError envelope too
+    conf.app_data(web::JsonConfig::default().error_handler(|e, _| AppError::from(e).into()))
+        .app_data(web::PathConfig::default().error_handler(|e, _| AppError::from(e).into()))
+        .app_data(web::QueryConfig::default().error_handler(|e, _| AppError::from(e).into()));
+    conf.service(jwks_handler);
     conf.service(
         web::scope("/api/auth")
             .service(user_registration_handler)
             .service(user_login_handler)
-            .service(user_logout_handler),
+            .service(mfa_login_handler)
+            .service(token_refresh_handler)
+            .service(user_logout_handler)
+            .service(verify_email_handler)
+            .service(resend_verification_handler)
+            .service(forgot_password_handler)
+            .service(reset_password_handler),
     );
     conf.service(
         web::scope("/api")
             .wrap(from_fn(jwt_middleware))
             .service(health_checker_handler)
+            .service(enroll_totp_handler)
+            .service(confirm_totp_handler)
+            .service(disable_totp_handler)
             .service(get_posts_handler)
+            .service(search_posts_handler)
+            .service(get_trash_handler)
+            .service(get_post_handler)
+            .service(get_post_by_slug_handler)
             .service(create_post_handler)
             .service(edit_post_handler)
-            .service(delete_post_handler),
+            .service(publish_post_handler)
+            .service(unpublish_post_handler)
+            .service(archive_post_handler)
+            .service(get_revisions_handler)
+            .service(diff_revisions_handler)
+            .service(get_revision_handler)
+            .service(restore_revision_handler)
+            .service(restore_post_handler)
+            .service(get_tags_handler)
+            .service(get_comments_handler)
+            .service(create_comment_handler)
+            .service(lock_comments_handler)
+            .service(unlock_comments_handler)
+            .service(edit_comment_handler)
+            .service(delete_comment_handler)
+            .service(add_reaction_handler)
+            .service(remove_reaction_handler)
+            .service(delete_post_handler)
+            .service(unlock_user_handler),
     );
 }
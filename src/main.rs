@@ -3,76 +3,208 @@ use actix_web::{http::header, middleware::Logger, web, App, HttpServer};
 use dotenvy::dotenv;
 use sqlx::postgres::PgPoolOptions;
 use sqlx::Executor;
-use std::env;
+use std::{env, io, process, time::Duration};
 
-use blog::config;
-use blog::AppState;
+use blog::{config, email_sender};
+use blog::{
+    publish_scheduled_posts, purge_trashed_posts, AppState, CommandLine, DatabaseSettings, JwtKeys,
+    KeyRingDir, Settings,
+};
 
-pub async fn create_run_migrations(
-    database_url: &str,
-    database_name: &str,
-) -> Result<(), sqlx::Error> {
+pub async fn create_run_migrations(database: &DatabaseSettings) -> Result<(), sqlx::Error> {
     let postgres_pool = PgPoolOptions::new()
-        .max_connections(5)
-        .connect(&database_url)
+        .max_connections(database.max_connections)
+        .connect(&database.url)
         .await?;
 
     let db_exist: bool =
         sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM pg_database WHERE datname = $1)")
-            .bind(database_name)
+            .bind(&database.name)
             .fetch_one(&postgres_pool)
             .await?;
 
     if !db_exist {
-        let create_db_query = format!("CREATE DATABASE {}", database_name);
+        let create_db_query = format!("CREATE DATABASE {}", database.name);
         postgres_pool.execute(&*create_db_query).await?;
     }
 
-    let target_database_url = format!("{}/{}", database_url, database_name);
     let pool = PgPoolOptions::new()
-        .max_connections(5)
-        .connect(&target_database_url)
+        .max_connections(database.max_connections)
+        .connect(&database.connection_url())
         .await?;
     sqlx::migrate!("./migrations").run(&pool).await?;
 
     Ok(())
 }
 
+// Administrative commands: `keys list`, `keys rotate` and `keys prune`
+fn run_command(settings: &Settings, args: &[String]) -> io::Result<()> {
+    let jwt = &settings.jwt;
+    let Some(dir) = &jwt.key_ring_dir else {
+        return Err(io::Error::other("key commands require jwt.key_ring_dir"));
+    };
+    let ring = KeyRingDir::new(dir, jwt.key_grace_period_days);
+
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["keys", "list"] => {
+            let now = chrono::Utc::now();
+            for record in ring.records().map_err(io::Error::other)? {
+                println!(
+                    "{}\t{:?}\tcreated {}\t{}",
+                    record.kid,
+                    record.algorithm,
+                    record.created_at.to_rfc3339(),
+                    ring.status(&record, now)
+                );
+            }
+        }
+        ["keys", "rotate"] => {
+            let record = ring.rotate(jwt.algorithm).map_err(io::Error::other)?;
+            println!("{} is now the signing key", record.kid);
+        }
+        ["keys", "prune"] => {
+            for record in ring.prune().map_err(io::Error::other)? {
+                println!("removed {}", record.kid);
+            }
+        }
+        _ => {
+            return Err(io::Error::other(format!(
+                "unknown command `{}`, expected `keys list`, `keys rotate` or `keys prune`",
+                args.join(" ")
+            )))
+        }
+    }
+
+    Ok(())
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     dotenv().ok();
 
     if env::var_os("RUST_LOG").is_none() {
-        env::set_var("RUST_LOG", "actix-web=info");
+        env::set_var("RUST_LOG", "actix-web=info,blog=info");
     }
 
     env_logger::init();
 
-    let database_name = "blog";
+    let (cli, settings) = match CommandLine::parse(env::args().skip(1))
+        .and_then(|cli| Settings::load(&cli).map(|settings| (cli, settings)))
+    {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    if !cli.args.is_empty() {
+        if let Err(e) = run_command(&settings, &cli.args) {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+        return Ok(());
+    }
 
-    let database_url = env::var("DATABASE_URL").expect("Database url must be set");
+    let keys = match JwtKeys::from_settings(&settings.jwt) {
+        Ok(keys) => keys,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
 
-    create_run_migrations(&database_url, database_name)
+    let email = match email_sender(&settings.email) {
+        Ok(email) => email,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    create_run_migrations(&settings.database)
         .await
         .expect("Database setup failed");
 
     let pool = PgPoolOptions::new()
-        .max_connections(5)
-        .connect(&database_url)
+        .max_connections(settings.database.max_connections)
+        .connect(&settings.database.connection_url())
         .await
         .expect("Database connection error");
 
-    let app_state = web::Data::new(AppState { pool });
+    let bind_address = (settings.server.host.clone(), settings.server.port);
+    let allowed_origins = settings.cors.allowed_origins.clone();
+    let app_state = web::Data::new(AppState {
+        pool,
+        settings,
+        keys,
+        email,
+    });
+
+    // Verify tokens signed by keys another process rotated in
+    if app_state.settings.jwt.key_ring_dir.is_some() {
+        let state = app_state.clone();
+        let period = Duration::from_secs(state.settings.jwt.key_reload_interval_secs);
+        actix_web::rt::spawn(async move {
+            let mut interval = actix_web::rt::time::interval(period);
+            loop {
+                interval.tick().await;
+                if let Err(e) = state.keys.reload() {
+                    log::error!("{}", e);
+                }
+            }
+        });
+    }
+
+    // Flip scheduled posts to published once their time has come
+    {
+        let state = app_state.clone();
+        let period = Duration::from_secs(state.settings.posts.publish_interval_secs);
+        actix_web::rt::spawn(async move {
+            let mut interval = actix_web::rt::time::interval(period);
+            loop {
+                interval.tick().await;
+                match publish_scheduled_posts(&state.pool).await {
+                    Ok(0) => {}
+                    Ok(count) => log::info!("published {} scheduled posts", count),
+                    Err(e) => log::error!("publishing scheduled posts failed: {}", e),
+                }
+            }
+        });
+    }
+
+    // Permanently remove posts that outstayed the trash retention period
+    {
+        let state = app_state.clone();
+        let period = Duration::from_secs(state.settings.posts.purge_interval_secs);
+        actix_web::rt::spawn(async move {
+            let mut interval = actix_web::rt::time::interval(period);
+            loop {
+                interval.tick().await;
+                let retention_days = state.settings.posts.trash_retention_days;
+                match purge_trashed_posts(&state.pool, retention_days).await {
+                    Ok(0) => {}
+                    Ok(count) => log::info!("purged {} trashed posts", count),
+                    Err(e) => log::error!("purging trashed posts failed: {}", e),
+                }
+            }
+        });
+    }
 
     HttpServer::new(move || {
-        let cors = Cors::default()
-            .allowed_origin("http://127.0.0.1:3000")
-            .allowed_methods(vec!["GET", "POST"])
+        let cors = allowed_origins
+            .iter()
+            .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin))
+            .allowed_methods(vec!["GET", "POST", "PUT", "PATCH", "DELETE"])
             .allowed_headers(vec![
                 header::CONTENT_TYPE,
                 header::AUTHORIZATION,
                 header::ACCEPT,
+                header::IF_MATCH,
+                header::IF_NONE_MATCH,
             ])
+            .expose_headers(vec![header::ETAG])
             .supports_credentials();
         App::new()
             .app_data(app_state.clone())
@@ -80,7 +212,7 @@ async fn main() -> std::io::Result<()> {
             .wrap(cors)
             .configure(config)
     })
-    .bind(("127.0.0.1", 8000))?
+    .bind(bind_address)?
     .run()
     .await
 }
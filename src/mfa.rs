@@ -0,0 +1,118 @@
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    queries::{
+        consume_recovery_code, get_mfa_challenge, get_mfa_challenge_for_update, get_totp_state,
+        insert_mfa_challenge, mark_mfa_challenge_used, record_mfa_attempt, record_totp_step,
+    },
+    totp::{is_totp_code, normalize_recovery_code, verify_code},
+    utils::{generate_token, hash_token},
+};
+
+// Wrong codes a pending login survives, the next one ends it
+pub const MAX_MFA_ATTEMPTS: i32 = 5;
+
+// Why a login could not be completed with a second factor
+#[derive(Debug)]
+pub enum MfaError {
+    Invalid,
+    WrongCode,
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for MfaError {
+    fn from(e: sqlx::Error) -> Self {
+        MfaError::Database(e)
+    }
+}
+
+// Token of a login waiting for its second factor, handed to the client
+pub struct IssuedMfaToken {
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+// Hold a login whose password was correct until the second factor is given
+pub async fn start_mfa_challenge(
+    pool: &PgPool,
+    user_id: &Uuid,
+    ttl_secs: i64,
+) -> sqlx::Result<IssuedMfaToken> {
+    let token = generate_token();
+    let created_at = Utc::now();
+    let expires_at = created_at + Duration::seconds(ttl_secs);
+
+    insert_mfa_challenge(
+        pool,
+        &Uuid::new_v4(),
+        user_id,
+        &hash_token(&token),
+        &created_at,
+        &expires_at,
+    )
+    .await?;
+
+    Ok(IssuedMfaToken { token, expires_at })
+}
+
+// User a pending login belongs to, so its attempts can be throttled before a code is checked
+pub async fn pending_mfa_user(pool: &PgPool, token: &str) -> Result<Uuid, MfaError> {
+    match get_mfa_challenge(pool, &hash_token(token)).await? {
+        Some(challenge) if challenge.used_at.is_none() && challenge.expires_at > Utc::now() => {
+            Ok(challenge.user_id)
+        }
+        _ => Err(MfaError::Invalid),
+    }
+}
+
+// Complete a pending login with a TOTP or recovery code, returning the user. Every code
+// works once, and too many wrong ones use the pending login up.
+pub async fn complete_mfa_challenge(
+    pool: &PgPool,
+    token: &str,
+    code: &str,
+) -> Result<Uuid, MfaError> {
+    let mut tx = pool.begin().await?;
+
+    let Some(challenge) = get_mfa_challenge_for_update(&mut tx, &hash_token(token)).await? else {
+        return Err(MfaError::Invalid);
+    };
+    if challenge.used_at.is_some() || challenge.expires_at <= Utc::now() {
+        return Err(MfaError::Invalid);
+    }
+
+    if check_second_factor(&mut tx, &challenge.user_id, code).await? {
+        mark_mfa_challenge_used(&mut tx, &challenge.id).await?;
+        tx.commit().await?;
+        return Ok(challenge.user_id);
+    }
+
+    let used_up = challenge.attempts + 1 >= MAX_MFA_ATTEMPTS;
+    record_mfa_attempt(&mut tx, &challenge.id, used_up).await?;
+    tx.commit().await?;
+    Err(MfaError::WrongCode)
+}
+
+// Check a TOTP or recovery code of a user with two-factor authentication on, using it up
+pub async fn check_second_factor(
+    conn: &mut PgConnection,
+    user_id: &Uuid,
+    code: &str,
+) -> sqlx::Result<bool> {
+    let totp = get_totp_state(conn, user_id).await?;
+    let (Some(secret), Some(_)) = (totp.secret, totp.enabled_at) else {
+        return Ok(false);
+    };
+
+    if is_totp_code(code) {
+        match verify_code(&secret, code, Utc::now().timestamp(), totp.last_step) {
+            Some(step) => record_totp_step(conn, user_id, step).await,
+            None => Ok(false),
+        }
+    } else {
+        let code_hash = hash_token(&normalize_recovery_code(code));
+        consume_recovery_code(conn, user_id, &code_hash).await
+    }
+}
@@ -1,101 +1,64 @@
 use actix_web::{
     body::MessageBody,
-    cookie::Cookie,
     dev::{ServiceRequest, ServiceResponse},
-    error,
-    http::header::{HeaderValue, SET_COOKIE},
+    http::header::AUTHORIZATION,
     middleware::Next,
-    HttpMessage,
+    web, HttpMessage,
 };
-use serde_json::json;
 
-use crate::utils::{decode_token, generate_access_token};
+use crate::error::AppError;
+use crate::settings::{TokenTransport, UnverifiedAccess};
+use crate::utils::decode_token;
+use crate::AppState;
 
+// Access token from the Authorization header or the access_token cookie, as the policy allows.
+// The header wins when both are accepted and present.
+fn access_token(req: &ServiceRequest, transport: TokenTransport) -> Option<String> {
+    let header = || {
+        let value = req.headers().get(AUTHORIZATION)?.to_str().ok()?;
+        let (scheme, token) = value.split_once(' ')?;
+        scheme
+            .eq_ignore_ascii_case("bearer")
+            .then(|| token.trim().to_string())
+    };
+    let cookie = || req.cookie("access_token").map(|c| c.value().to_string());
+
+    match transport {
+        TokenTransport::Cookie => cookie(),
+        TokenTransport::Header => header(),
+        TokenTransport::Either => header().or_else(cookie),
+    }
+}
+
+// Reject requests without a valid access token. Expired tokens are not refreshed here,
+// clients exchange their refresh token at /api/auth/refresh when they see `token_expired`.
 pub async fn jwt_middleware(
     req: ServiceRequest,
     next: Next<impl MessageBody>,
 ) -> actix_web::Result<ServiceResponse<impl MessageBody>> {
-    match req.cookie("access_token") {
-        Some(token) => {
-            let access_token = token.value().to_string();
-            match decode_token(&access_token, "secret") {
-                Ok(claims) => {
-                    // Insert the claims into the request extensions
-                    req.extensions_mut().insert(claims.claims);
-                    return next.call(req).await;
-                }
-                Err(error) => match error.kind() {
-                    jsonwebtoken::errors::ErrorKind::ExpiredSignature => {
-                        match req.cookie("refresh_token") {
-                            Some(token) => {
-                                let refresh_token = token.value().to_string();
-                                match decode_token(&refresh_token, "secret") {
-                                    Ok(claims) => {
-                                        // Generate a new access token
-                                        let new_access_token = generate_access_token(
-                                            &claims.claims.sub,
-                                            "secret",
-                                        )
-                                        .map_err(|_| {
-                                            error::ErrorInternalServerError(json!({
-                                                "error": "Error generating new access token!"
-                                            }))
-                                        })?;
+    let state = req
+        .app_data::<web::Data<AppState>>()
+        .cloned()
+        .ok_or_else(|| AppError::Internal("application state is not configured".to_string()))?;
 
-                                        // Create a new cookie for the access token
-                                        let cookie =
-                                            Cookie::build("access_token", new_access_token.clone())
-                                                .http_only(true)
-                                                .path("/")
-                                                .finish();
+    let access_token =
+        access_token(&req, state.settings.auth.token_transport).ok_or_else(|| {
+            AppError::Unauthorized("token_missing", "Missing access token".to_string())
+        })?;
+    let claims = decode_token(&access_token, &state.keys).map_err(AppError::from)?;
 
-                                        // Add the claims to the request
-                                        req.extensions_mut().insert(claims.claims);
-
-                                        // Call the next service
-                                        let mut response = next.call(req).await?;
-
-                                        // Add the cookie to the response headers
-                                        response.response_mut().headers_mut().insert(
-                                            SET_COOKIE,
-                                            HeaderValue::from_str(&cookie.to_string()).map_err(
-                                                |_| {
-                                                    error::ErrorInternalServerError(
-                                                        "Invalid cookie",
-                                                    )
-                                                },
-                                            )?,
-                                        );
-
-                                        return Ok(response);
-                                    }
-                                    Err(e) => {
-                                        return Err(error::ErrorUnauthorized(
-                                            json!({"error": format!("Invalid or expired refresh token: {}", e)}),
-                                        ));
-                                    }
-                                }
-                            }
-                            None => {
-                                return Err(error::ErrorUnauthorized(
-                                    json!({"error": "Missing refresh token"}),
-                                ));
-                            }
-                        }
-                    }
-                    jsonwebtoken::errors::ErrorKind::InvalidSignature => {
-                        return Err(error::ErrorUnauthorized(
-                            json!({"error": "Invalid token. Please try again!"}),
-                        ));
-                    }
-                    _ => {
-                        return Err(error::ErrorUnauthorized(json!({"error": "Token error!"})));
-                    }
-                },
-            }
-        }
-        None => {
-            return Err(error::ErrorUnauthorized("Missing access token"));
-        }
+    // Accounts that may only read until they verify their email address
+    if !claims.claims.email_verified
+        && state.settings.auth.unverified_access == UnverifiedAccess::Read
+        && !req.method().is_safe()
+    {
+        return Err(AppError::EmailUnverified(
+            "Verify your email address to make changes!".to_string(),
+        )
+        .into());
     }
+
+    // Insert the claims into the request extensions
+    req.extensions_mut().insert(claims.claims);
+    next.call(req).await
 }
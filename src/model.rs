@@ -1,10 +1,26 @@
+use std::{collections::BTreeMap, sync::Arc};
+
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::PgPool;
+use sqlx::{types::Json, FromRow, PgPool};
 use uuid::Uuid;
+
+use crate::{
+    email::EmailSender,
+    error::{AppError, FieldError},
+    keys::JwtKeys,
+    pagination::{Page, PostSort, SortOrder},
+    search::SearchTerms,
+    settings::Settings,
+    tags::{normalize_tags, TagMatch, TagQuery, MAX_TAGS, MAX_TAG_LENGTH},
+};
+
 //App state
 pub struct AppState {
     pub pool: PgPool,
+    pub settings: Settings,
+    pub keys: JwtKeys,
+    pub email: Arc<dyn EmailSender>,
 }
 
 // Token claim
@@ -13,6 +29,11 @@ pub struct Claim {
     pub sub: String,
     pub iat: usize,
     pub exp: usize,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    // Whether the email address was verified when the token was issued
+    #[serde(default)]
+    pub email_verified: bool,
 }
 
 // User model
@@ -22,6 +43,9 @@ pub struct User {
     pub name: String,
     pub email: String,
     pub password: String,
+    pub email_verified_at: Option<DateTime<Utc>>,
+    // Set while two-factor authentication is on
+    pub totp_enabled_at: Option<DateTime<Utc>>,
 }
 
 //User registration model
@@ -32,11 +56,114 @@ pub struct UserRegistration {
     pub password: String,
 }
 
+impl UserRegistration {
+    pub fn validate(&self) -> Result<(), AppError> {
+        let mut errors = Vec::new();
+        check(
+            &mut errors,
+            "name",
+            !self.name.trim().is_empty(),
+            "must not be empty",
+        );
+        check(
+            &mut errors,
+            "name",
+            self.name.chars().count() <= 255,
+            "must be at most 255 characters",
+        );
+        check(
+            &mut errors,
+            "email",
+            is_email(&self.email),
+            "must be a valid email address",
+        );
+        check(
+            &mut errors,
+            "email",
+            self.email.chars().count() <= 255,
+            "must be at most 255 characters",
+        );
+        check_password(&mut errors, &self.password);
+        into_result(errors)
+    }
+}
+
 //User login model
 #[derive(Debug, Deserialize, Serialize)]
 pub struct UserLogin {
     pub email: String,
     pub password: String,
+    // Return the tokens in the response body instead of cookies
+    #[serde(default)]
+    pub include_tokens: bool,
+}
+
+//Token refresh and logout model, the refresh_token cookie is used when absent
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct RefreshRequest {
+    pub refresh_token: Option<String>,
+    #[serde(default)]
+    pub include_tokens: bool,
+}
+
+//Email verification model, the token comes from the emailed link
+#[derive(Debug, Deserialize, Serialize)]
+pub struct VerifyEmailRequest {
+    pub token: String,
+}
+
+//Model for requesting another verification email
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ResendVerificationRequest {
+    pub email: String,
+}
+
+//Model for requesting a password reset email
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ForgotPasswordRequest {
+    pub email: String,
+}
+
+//Password reset model, the token comes from the emailed link
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub password: String,
+}
+
+impl ResetPasswordRequest {
+    pub fn validate(&self) -> Result<(), AppError> {
+        let mut errors = Vec::new();
+        check_password(&mut errors, &self.password);
+        into_result(errors)
+    }
+}
+
+//Second step of a login with two-factor authentication
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MfaLogin {
+    pub mfa_token: String,
+    // Code of the authenticator app or one of the recovery codes
+    pub code: String,
+    #[serde(default)]
+    pub include_tokens: bool,
+}
+
+//Model for confirming a TOTP enrollment with a first code
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TotpConfirmation {
+    pub password: String,
+    pub code: String,
+}
+
+//Model for turning two-factor authentication off
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TotpDisable {
+    pub password: String,
+    // Code of the authenticator app or one of the recovery codes, not needed to drop an
+    // enrollment that was never confirmed
+    #[serde(default)]
+    pub code: String,
 }
 
 //User response model
@@ -48,14 +175,286 @@ pub struct UserResponse {
 }
 
 // Post model
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, FromRow)]
 pub struct Post {
     pub id: Uuid,
     pub user_id: Uuid,
     pub title: String,
+    // URL-safe name derived from the title, old ones keep redirecting after a title change
+    pub slug: String,
     pub content: String,
-    pub created_at: Option<DateTime<Utc>>,
-    pub updated_at: Option<DateTime<Utc>>,
+    // Normalized and sorted
+    pub tags: Vec<String>,
+    // Comments that are not deleted
+    pub comment_count: i64,
+    // Set by the author to stop new comments and edits of existing ones
+    pub comments_locked: bool,
+    // Reactions per kind and in total, which is what popularity sorts by
+    pub reactions: ReactionCounts,
+    pub reaction_count: i64,
+    // Kinds the caller reacted with, empty when they have not reacted
+    pub reacted: Vec<ReactionKind>,
+    pub status: PostStatus,
+    pub published_at: Option<DateTime<Utc>>,
+    // Bumped on every change, the post's ETag
+    pub version: i64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+// Fixed set of reactions to a post
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize, sqlx::Type,
+)]
+#[sqlx(type_name = "reaction_kind", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum ReactionKind {
+    Like,
+    Love,
+    Laugh,
+    Wow,
+    Sad,
+    Angry,
+}
+
+// Number of reactions of each kind a post got, kinds nobody used are left out
+pub type ReactionCounts = Json<BTreeMap<ReactionKind, i64>>;
+
+// Lifecycle of a post. Drafts and archived posts are only visible to their author, scheduled
+// posts become visible once `published_at` has passed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, sqlx::Type)]
+#[sqlx(type_name = "post_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum PostStatus {
+    Draft,
+    Published,
+    Scheduled,
+    Archived,
+}
+
+// Post with its author embedded in place of user_id, for `?expand=author`
+#[derive(Debug, Serialize)]
+pub struct PostWithAuthor {
+    pub id: Uuid,
+    pub author: UserResponse,
+    pub title: String,
+    pub slug: String,
+    pub content: String,
+    pub tags: Vec<String>,
+    pub comment_count: i64,
+    pub comments_locked: bool,
+    pub reactions: ReactionCounts,
+    pub reaction_count: i64,
+    pub reacted: Vec<ReactionKind>,
+    pub status: PostStatus,
+    pub published_at: Option<DateTime<Utc>>,
+    pub version: i64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+// Title and content of a post before one of its edits
+#[derive(Debug, Serialize)]
+pub struct PostRevision {
+    pub id: Uuid,
+    pub post_id: Uuid,
+    pub revision: i32,
+    pub editor_id: Uuid,
+    pub title: String,
+    pub content: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// Revision as listed, without its content
+#[derive(Debug, Serialize)]
+pub struct PostRevisionSummary {
+    pub id: Uuid,
+    pub revision: i32,
+    pub editor_id: Uuid,
+    pub title: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// Query string of the revision diff, `to` defaults to the current post
+#[derive(Debug, Deserialize)]
+pub struct RevisionDiffQuery {
+    pub from: i32,
+    pub to: Option<i32>,
+}
+
+// Query string of the single post endpoint
+#[derive(Debug, Deserialize)]
+pub struct PostQuery {
+    pub expand: Option<String>,
+}
+
+impl PostQuery {
+    // Whether the author should be embedded. `expand` takes a comma separated list.
+    pub fn expand_author(&self) -> Result<bool, AppError> {
+        let mut author = false;
+        for field in self.expand.iter().flat_map(|expand| expand.split(',')) {
+            match field.trim() {
+                "author" => author = true,
+                "" => {}
+                other => {
+                    return Err(AppError::validation(
+                        "expand",
+                        &format!("cannot expand `{}`", other),
+                    ))
+                }
+            }
+        }
+        Ok(author)
+    }
+}
+
+// Query string of the post listing
+#[derive(Debug, Deserialize)]
+pub struct PostListQuery {
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+    pub sort: Option<PostSort>,
+    pub order: Option<SortOrder>,
+    pub author: Option<Uuid>,
+    pub status: Option<PostStatus>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+    // How the repeated `tag` parameters combine
+    pub tag_match: Option<TagMatch>,
+}
+
+impl PostListQuery {
+    pub fn page(&self) -> Result<Page, AppError> {
+        Page::new(
+            self.limit,
+            self.cursor.as_deref(),
+            self.sort,
+            self.order,
+            false,
+        )
+    }
+
+    pub fn filter(&self, tags: &TagQuery) -> Result<PostFilter, AppError> {
+        PostFilter::new(
+            self.author,
+            self.status,
+            self.created_after,
+            self.created_before,
+            tags,
+            self.tag_match,
+        )
+    }
+}
+
+// Query string of the post search, the listing parameters plus `q`
+#[derive(Debug, Deserialize)]
+pub struct PostSearchQuery {
+    pub q: String,
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+    pub sort: Option<PostSort>,
+    pub order: Option<SortOrder>,
+    pub author: Option<Uuid>,
+    pub status: Option<PostStatus>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+    // How the repeated `tag` parameters combine
+    pub tag_match: Option<TagMatch>,
+}
+
+impl PostSearchQuery {
+    pub fn terms(&self) -> Result<SearchTerms, AppError> {
+        SearchTerms::parse(&self.q)
+    }
+
+    pub fn page(&self) -> Result<Page, AppError> {
+        Page::new(
+            self.limit,
+            self.cursor.as_deref(),
+            self.sort,
+            self.order,
+            true,
+        )
+    }
+
+    pub fn filter(&self, tags: &TagQuery) -> Result<PostFilter, AppError> {
+        PostFilter::new(
+            self.author,
+            self.status,
+            self.created_after,
+            self.created_before,
+            tags,
+            self.tag_match,
+        )
+    }
+}
+
+// Search hit with its relevance and a highlighted excerpt of the content
+#[derive(Debug, Serialize, FromRow)]
+pub struct PostSearchResult {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub post: Post,
+    pub rank: f32,
+    pub headline: String,
+}
+
+// Post in its author's trash
+#[derive(Debug, Serialize, FromRow)]
+pub struct TrashedPost {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub post: Post,
+    pub deleted_at: DateTime<Utc>,
+    // When the purge removes it for good
+    pub purge_at: DateTime<Utc>,
+}
+
+// Tag with the number of posts the caller can see carrying it
+#[derive(Debug, Serialize)]
+pub struct TagCount {
+    pub name: String,
+    pub count: i64,
+}
+
+// Restricts which posts a listing returns
+#[derive(Debug, Default)]
+pub struct PostFilter {
+    pub author: Option<Uuid>,
+    pub status: Option<PostStatus>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+    pub tags: Vec<String>,
+    pub tag_match: TagMatch,
+}
+
+impl PostFilter {
+    pub fn new(
+        author: Option<Uuid>,
+        status: Option<PostStatus>,
+        created_after: Option<DateTime<Utc>>,
+        created_before: Option<DateTime<Utc>>,
+        tags: &TagQuery,
+        tag_match: Option<TagMatch>,
+    ) -> Result<PostFilter, AppError> {
+        if let (Some(after), Some(before)) = (created_after, created_before) {
+            if after > before {
+                return Err(AppError::validation(
+                    "created_after",
+                    "must not be later than created_before",
+                ));
+            }
+        }
+
+        Ok(PostFilter {
+            author,
+            status,
+            created_after,
+            created_before,
+            tags: tags.normalized()?,
+            tag_match: tag_match.unwrap_or_default(),
+        })
+    }
 }
 
 // Struct for creating new Post
@@ -63,6 +462,66 @@ pub struct Post {
 pub struct NewPost {
     pub title: String,
     pub content: String,
+    // Posts start as drafts unless published or scheduled right away
+    pub status: Option<PostStatus>,
+    pub published_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl NewPost {
+    pub fn validate(&self) -> Result<(), AppError> {
+        let mut errors = Vec::new();
+        check_title(&mut errors, &self.title);
+        check(
+            &mut errors,
+            "content",
+            !self.content.trim().is_empty(),
+            "must not be empty",
+        );
+        check(
+            &mut errors,
+            "status",
+            self.status != Some(PostStatus::Archived),
+            "must be draft, published or scheduled",
+        );
+        check_publish_at(
+            &mut errors,
+            self.status == Some(PostStatus::Scheduled),
+            self.published_at,
+        );
+        check_tags(&mut errors, &self.tags);
+        into_result(errors)
+    }
+
+    pub fn tags(&self) -> Vec<String> {
+        normalize_tags(&self.tags)
+    }
+
+    // Status and publish time the post is created with
+    pub fn publication(&self) -> (PostStatus, Option<DateTime<Utc>>) {
+        match self.status.unwrap_or(PostStatus::Draft) {
+            PostStatus::Published => (PostStatus::Published, Some(Utc::now())),
+            PostStatus::Scheduled => (PostStatus::Scheduled, self.published_at),
+            status => (status, None),
+        }
+    }
+}
+
+// Body of `POST /api/posts/{id}/publish`, a future `published_at` schedules the post
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct PublishPost {
+    pub published_at: Option<DateTime<Utc>>,
+}
+
+impl PublishPost {
+    // Status and publish time the post moves to
+    pub fn publication(&self) -> (PostStatus, DateTime<Utc>) {
+        match self.published_at {
+            Some(at) if at > Utc::now() => (PostStatus::Scheduled, at),
+            _ => (PostStatus::Published, Utc::now()),
+        }
+    }
 }
 
 // Struct for updating existing Post
@@ -70,4 +529,240 @@ pub struct NewPost {
 pub struct UpdatePost {
     pub title: Option<String>,
     pub content: Option<String>,
+    // Replaces all tags of the post when given
+    pub tags: Option<Vec<String>>,
+}
+
+impl UpdatePost {
+    pub fn validate(&self) -> Result<(), AppError> {
+        let mut errors = Vec::new();
+        if let Some(title) = &self.title {
+            check_title(&mut errors, title);
+        }
+        if let Some(content) = &self.content {
+            check(
+                &mut errors,
+                "content",
+                !content.trim().is_empty(),
+                "must not be empty",
+            );
+        }
+        if let Some(tags) = &self.tags {
+            check_tags(&mut errors, tags);
+        }
+        into_result(errors)
+    }
+
+    pub fn tags(&self) -> Option<Vec<String>> {
+        self.tags.as_deref().map(normalize_tags)
+    }
+}
+
+// Comment on a post. Deleted comments stay as placeholders without their content while they
+// have replies.
+#[derive(Debug, Serialize)]
+pub struct Comment {
+    pub id: Uuid,
+    pub post_id: Uuid,
+    pub user_id: Uuid,
+    pub parent_id: Option<Uuid>,
+    // Number of ancestors, 0 for top-level comments
+    pub depth: i32,
+    pub content: Option<String>,
+    pub deleted: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+// Body of `POST /api/posts/{id}/comments`, with `parent_id` to reply to another comment
+#[derive(Debug, Deserialize)]
+pub struct NewComment {
+    pub content: String,
+    pub parent_id: Option<Uuid>,
+}
+
+impl NewComment {
+    pub fn validate(&self) -> Result<(), AppError> {
+        let mut errors = Vec::new();
+        check_comment(&mut errors, &self.content);
+        into_result(errors)
+    }
+}
+
+// Body of `PATCH /api/comments/{id}`
+#[derive(Debug, Deserialize)]
+pub struct UpdateComment {
+    pub content: String,
+}
+
+impl UpdateComment {
+    pub fn validate(&self) -> Result<(), AppError> {
+        let mut errors = Vec::new();
+        check_comment(&mut errors, &self.content);
+        into_result(errors)
+    }
+}
+
+// How `GET /api/posts/{id}/comments` lays out a thread
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CommentView {
+    // Replies nested inside the comment they answer
+    #[default]
+    Tree,
+    // One list in thread order, each comment with its depth
+    Flat,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CommentListQuery {
+    pub view: Option<CommentView>,
+}
+
+// Server side refresh token, only the hash of the token is stored
+#[derive(Debug)]
+pub struct RefreshToken {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub family_id: Uuid,
+    pub expires_at: DateTime<Utc>,
+    pub rotated_at: Option<DateTime<Utc>>,
+    pub reissued_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+// Two-factor authentication of a user. A secret without `enabled_at` is a pending enrollment.
+#[derive(Debug)]
+pub struct TotpState {
+    pub secret: Option<Vec<u8>>,
+    pub enabled_at: Option<DateTime<Utc>>,
+    pub last_step: Option<i64>,
+}
+
+// Failed logins of an account or client address
+#[derive(Debug)]
+pub struct LoginThrottle {
+    pub failures: i32,
+    pub last_failed_at: Option<DateTime<Utc>>,
+    pub locked_until: Option<DateTime<Utc>>,
+}
+
+// Login waiting for its second factor, only the hash of its token is stored
+#[derive(Debug)]
+pub struct MfaChallenge {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub expires_at: DateTime<Utc>,
+    pub attempts: i32,
+    pub used_at: Option<DateTime<Utc>>,
+}
+
+fn check(errors: &mut Vec<FieldError>, field: &str, valid: bool, message: &str) {
+    if !valid {
+        errors.push(FieldError {
+            field: field.to_string(),
+            message: message.to_string(),
+        });
+    }
+}
+
+// Scheduling needs a publish time that has not passed yet
+fn check_publish_at(
+    errors: &mut Vec<FieldError>,
+    scheduling: bool,
+    published_at: Option<DateTime<Utc>>,
+) {
+    if !scheduling {
+        return;
+    }
+    match published_at {
+        None => check(errors, "published_at", false, "is required to schedule"),
+        Some(at) => check(
+            errors,
+            "published_at",
+            at > Utc::now(),
+            "must be in the future to schedule",
+        ),
+    }
+}
+
+fn check_title(errors: &mut Vec<FieldError>, title: &str) {
+    check(
+        errors,
+        "title",
+        !title.trim().is_empty(),
+        "must not be empty",
+    );
+    check(
+        errors,
+        "title",
+        title.chars().count() <= 255,
+        "must be at most 255 characters",
+    );
+}
+
+fn check_tags(errors: &mut Vec<FieldError>, tags: &[String]) {
+    let tags = normalize_tags(tags);
+    check(
+        errors,
+        "tags",
+        tags.iter().all(|tag| !tag.is_empty()),
+        "must not contain empty tags",
+    );
+    check(
+        errors,
+        "tags",
+        tags.iter().all(|tag| tag.chars().count() <= MAX_TAG_LENGTH),
+        &format!(
+            "must only contain tags of at most {} characters",
+            MAX_TAG_LENGTH
+        ),
+    );
+    check(
+        errors,
+        "tags",
+        tags.len() <= MAX_TAGS,
+        &format!("must contain at most {} different tags", MAX_TAGS),
+    );
+}
+
+fn check_comment(errors: &mut Vec<FieldError>, content: &str) {
+    check(
+        errors,
+        "content",
+        !content.trim().is_empty(),
+        "must not be empty",
+    );
+    check(
+        errors,
+        "content",
+        content.chars().count() <= 10_000,
+        "must be at most 10000 characters",
+    );
+}
+
+fn check_password(errors: &mut Vec<FieldError>, password: &str) {
+    check(
+        errors,
+        "password",
+        password.chars().count() >= 8,
+        "must be at least 8 characters",
+    );
+}
+
+fn is_email(email: &str) -> bool {
+    match email.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty() && domain.contains('.') && !domain.starts_with('.')
+        }
+        None => false,
+    }
+}
+
+fn into_result(errors: Vec<FieldError>) -> Result<(), AppError> {
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(AppError::Validation(errors))
+    }
 }
@@ -0,0 +1,421 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Postgres, QueryBuilder};
+use uuid::Uuid;
+
+use crate::{
+    error::AppError,
+    model::{Post, PostSearchResult},
+};
+
+pub const DEFAULT_LIMIT: i64 = 20;
+pub const MAX_LIMIT: i64 = 100;
+
+// Column a post listing is ordered by, ties are broken by id
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PostSort {
+    #[default]
+    Created,
+    Updated,
+    Title,
+    // Total number of reactions
+    Popularity,
+    // Only meaningful for searches, see `queries::search_posts`
+    Relevance,
+}
+
+impl PostSort {
+    fn column(self) -> &'static str {
+        match self {
+            PostSort::Created => "created_at",
+            PostSort::Updated => "updated_at",
+            PostSort::Title => "title",
+            PostSort::Popularity => "reaction_count",
+            PostSort::Relevance => "rank",
+        }
+    }
+
+    // Newest, most popular and best matches first, titles alphabetical
+    fn default_order(self) -> SortOrder {
+        match self {
+            PostSort::Created | PostSort::Updated | PostSort::Popularity | PostSort::Relevance => {
+                SortOrder::Desc
+            }
+            PostSort::Title => SortOrder::Asc,
+        }
+    }
+}
+
+// Rows a page can be cut from
+pub trait Keyset {
+    fn id(&self) -> Uuid;
+    fn sort_key(&self, sort: PostSort) -> String;
+}
+
+impl Keyset for Post {
+    fn id(&self) -> Uuid {
+        self.id
+    }
+
+    fn sort_key(&self, sort: PostSort) -> String {
+        match sort {
+            PostSort::Created => self.created_at.to_rfc3339(),
+            PostSort::Updated => self.updated_at.to_rfc3339(),
+            PostSort::Title => self.title.clone(),
+            PostSort::Popularity => self.reaction_count.to_string(),
+            // Plain posts carry no rank, relevance is rejected for them in `Page::new`
+            PostSort::Relevance => String::new(),
+        }
+    }
+}
+
+impl Keyset for PostSearchResult {
+    fn id(&self) -> Uuid {
+        self.post.id
+    }
+
+    fn sort_key(&self, sort: PostSort) -> String {
+        match sort {
+            PostSort::Relevance => self.rank.to_string(),
+            sort => self.post.sort_key(sort),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+// Position of the last post handed out. Clients get it base64url encoded and must treat it as
+// opaque; it is only valid for the sort and order it was issued for.
+#[derive(Debug, Deserialize, Serialize)]
+struct Cursor {
+    sort: PostSort,
+    order: SortOrder,
+    key: String,
+    id: Uuid,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    fn decode(value: &str) -> Option<Cursor> {
+        let bytes = URL_SAFE_NO_PAD.decode(value).ok()?;
+        serde_json::from_slice(&bytes).ok()
+    }
+}
+
+// A validated request for one page of posts
+#[derive(Debug)]
+pub struct Page {
+    pub limit: i64,
+    pub sort: PostSort,
+    pub order: SortOrder,
+    after: Option<(SortKey, Uuid)>,
+}
+
+#[derive(Debug)]
+enum SortKey {
+    Time(DateTime<Utc>),
+    Text(String),
+    Count(i64),
+    Rank(f32),
+}
+
+impl Page {
+    // `searching` allows and defaults to relevance ordering
+    pub fn new(
+        limit: Option<i64>,
+        cursor: Option<&str>,
+        sort: Option<PostSort>,
+        order: Option<SortOrder>,
+        searching: bool,
+    ) -> Result<Page, AppError> {
+        let limit = limit.unwrap_or(DEFAULT_LIMIT);
+        if !(1..=MAX_LIMIT).contains(&limit) {
+            return Err(AppError::validation(
+                "limit",
+                &format!("must be between 1 and {}", MAX_LIMIT),
+            ));
+        }
+
+        let sort = match sort {
+            Some(PostSort::Relevance) if !searching => {
+                return Err(AppError::validation(
+                    "sort",
+                    "relevance is only available when searching",
+                ))
+            }
+            Some(sort) => sort,
+            None if searching => PostSort::Relevance,
+            None => PostSort::default(),
+        };
+        let order = order.unwrap_or(sort.default_order());
+
+        let after = match cursor {
+            None => None,
+            Some(value) => {
+                let invalid = || AppError::validation("cursor", "is not a valid cursor");
+                let cursor = Cursor::decode(value).ok_or_else(invalid)?;
+                if cursor.sort != sort || cursor.order != order {
+                    return Err(AppError::validation(
+                        "cursor",
+                        "was issued for a different sort or order",
+                    ));
+                }
+                let key = match sort {
+                    PostSort::Created | PostSort::Updated => SortKey::Time(
+                        DateTime::parse_from_rfc3339(&cursor.key)
+                            .map_err(|_| invalid())?
+                            .with_timezone(&Utc),
+                    ),
+                    PostSort::Title => SortKey::Text(cursor.key),
+                    PostSort::Popularity => {
+                        SortKey::Count(cursor.key.parse().map_err(|_| invalid())?)
+                    }
+                    PostSort::Relevance => {
+                        SortKey::Rank(cursor.key.parse().map_err(|_| invalid())?)
+                    }
+                };
+                Some((key, cursor.id))
+            }
+        };
+
+        Ok(Page {
+            limit,
+            sort,
+            order,
+            after,
+        })
+    }
+
+    // Append the keyset condition, ordering and limit to a query whose WHERE clause is open.
+    // One row more than the limit is fetched to tell whether another page follows.
+    pub fn push_keyset(&self, query: &mut QueryBuilder<'_, Postgres>, table: &str) {
+        let column = self.sort.column();
+        let (comparison, direction) = match self.order {
+            SortOrder::Asc => (">", "ASC"),
+            SortOrder::Desc => ("<", "DESC"),
+        };
+
+        if let Some((key, id)) = &self.after {
+            query.push(format!(
+                " AND ({table}.{column}, {table}.id) {comparison} ("
+            ));
+            match key {
+                SortKey::Time(time) => query.push_bind(*time),
+                SortKey::Text(text) => query.push_bind(text.clone()),
+                SortKey::Count(count) => query.push_bind(*count),
+                SortKey::Rank(rank) => query.push_bind(*rank),
+            };
+            query.push(", ").push_bind(*id).push(")");
+        }
+
+        query
+            .push(format!(
+                " ORDER BY {table}.{column} {direction}, {table}.id {direction} LIMIT "
+            ))
+            .push_bind(self.limit + 1);
+    }
+
+    // Trim the extra row fetched by `push_keyset` and build the cursor of the next page
+    pub fn finish<T: Keyset>(&self, rows: &mut Vec<T>) -> Option<String> {
+        if rows.len() as i64 <= self.limit {
+            return None;
+        }
+        rows.truncate(self.limit as usize);
+
+        rows.last().map(|row| {
+            Cursor {
+                sort: self.sort,
+                order: self.order,
+                key: row.sort_key(self.sort),
+                id: row.id(),
+            }
+            .encode()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Row {
+        id: Uuid,
+        title: String,
+    }
+
+    impl Keyset for Row {
+        fn id(&self) -> Uuid {
+            self.id
+        }
+
+        fn sort_key(&self, _: PostSort) -> String {
+            self.title.clone()
+        }
+    }
+
+    fn rows(count: usize) -> Vec<Row> {
+        (0..count)
+            .map(|i| Row {
+                id: Uuid::new_v4(),
+                title: format!("post {}", i),
+            })
+            .collect()
+    }
+
+    fn cursor(sort: PostSort, order: SortOrder, key: &str) -> String {
+        Cursor {
+            sort,
+            order,
+            key: key.to_string(),
+            id: Uuid::new_v4(),
+        }
+        .encode()
+    }
+
+    // Field and message of a single validation failure
+    fn rejection(result: Result<Page, AppError>) -> (String, String) {
+        match result {
+            Err(AppError::Validation(errors)) if errors.len() == 1 => {
+                (errors[0].field.clone(), errors[0].message.clone())
+            }
+            other => panic!("expected a validation failure, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn cursors_round_trip() {
+        let original = Cursor {
+            sort: PostSort::Popularity,
+            order: SortOrder::Asc,
+            key: "42".to_string(),
+            id: Uuid::new_v4(),
+        };
+        let decoded = Cursor::decode(&original.encode()).unwrap();
+        assert_eq!(decoded.sort, original.sort);
+        assert_eq!(decoded.order, original.order);
+        assert_eq!(decoded.key, original.key);
+        assert_eq!(decoded.id, original.id);
+    }
+
+    #[test]
+    fn finish_issues_a_cursor_only_when_another_page_follows() {
+        let page = Page::new(Some(2), None, Some(PostSort::Title), None, false).unwrap();
+
+        let mut exact = rows(2);
+        assert_eq!(page.finish(&mut exact), None);
+        assert_eq!(exact.len(), 2);
+
+        let mut more = rows(3);
+        let next = page.finish(&mut more).unwrap();
+        assert_eq!(more.len(), 2);
+
+        let next_page =
+            Page::new(Some(2), Some(&next), Some(PostSort::Title), None, false).unwrap();
+        match next_page.after {
+            Some((SortKey::Text(title), id)) => {
+                assert_eq!(title, more[1].title);
+                assert_eq!(id, more[1].id);
+            }
+            other => panic!("unexpected position {:?}", other),
+        }
+    }
+
+    #[test]
+    fn cursors_are_only_valid_for_their_sort_and_order() {
+        let created = cursor(PostSort::Created, SortOrder::Desc, &Utc::now().to_rfc3339());
+        assert!(Page::new(None, Some(&created), None, None, false).is_ok());
+
+        let (field, message) = rejection(Page::new(
+            None,
+            Some(&created),
+            Some(PostSort::Title),
+            None,
+            false,
+        ));
+        assert_eq!(field, "cursor");
+        assert_eq!(message, "was issued for a different sort or order");
+
+        let (field, _) = rejection(Page::new(
+            None,
+            Some(&created),
+            None,
+            Some(SortOrder::Asc),
+            false,
+        ));
+        assert_eq!(field, "cursor");
+    }
+
+    #[test]
+    fn garbage_and_tampered_cursors_are_rejected() {
+        let tampered = cursor(PostSort::Created, SortOrder::Desc, "yesterday");
+        let mut flipped = cursor(PostSort::Title, SortOrder::Asc, "a").into_bytes();
+        flipped[3] ^= 1;
+        let flipped = String::from_utf8(flipped).unwrap();
+        let not_json = URL_SAFE_NO_PAD.encode("not json");
+
+        for value in ["", "!!!", "bm90IGpzb24=", &not_json, &flipped, &tampered] {
+            let (field, message) = rejection(Page::new(None, Some(value), None, None, false));
+            assert_eq!(field, "cursor");
+            assert_eq!(message, "is not a valid cursor", "cursor {:?}", value);
+        }
+
+        let count = cursor(PostSort::Popularity, SortOrder::Desc, "many");
+        let (field, _) = rejection(Page::new(
+            None,
+            Some(&count),
+            Some(PostSort::Popularity),
+            None,
+            false,
+        ));
+        assert_eq!(field, "cursor");
+    }
+
+    #[test]
+    fn limit_must_be_within_bounds() {
+        assert_eq!(
+            Page::new(None, None, None, None, false).unwrap().limit,
+            DEFAULT_LIMIT
+        );
+        assert_eq!(
+            Page::new(Some(1), None, None, None, false).unwrap().limit,
+            1
+        );
+        assert_eq!(
+            Page::new(Some(MAX_LIMIT), None, None, None, false)
+                .unwrap()
+                .limit,
+            MAX_LIMIT
+        );
+
+        for limit in [0, -1, MAX_LIMIT + 1] {
+            let (field, _) = rejection(Page::new(Some(limit), None, None, None, false));
+            assert_eq!(field, "limit");
+        }
+    }
+
+    #[test]
+    fn relevance_is_reserved_for_searches() {
+        let search = Page::new(None, None, None, None, true).unwrap();
+        assert_eq!(search.sort, PostSort::Relevance);
+        assert_eq!(search.order, SortOrder::Desc);
+
+        let (field, _) = rejection(Page::new(
+            None,
+            None,
+            Some(PostSort::Relevance),
+            None,
+            false,
+        ));
+        assert_eq!(field, "sort");
+    }
+}
@@ -0,0 +1,83 @@
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+
+use crate::{
+    email::{send_in_background, RESET_PASSWORD},
+    queries::{
+        consume_password_reset_token, insert_password_reset_token, last_password_reset_token_at,
+        revoke_user_refresh_tokens, set_user_password,
+    },
+    utils::{generate_token, hash_token},
+    AppState,
+};
+
+// Shortest time between two password reset emails to the same account
+pub const RESET_COOLDOWN_SECS: i64 = 60;
+
+// Email a user a link to choose a new password with. Links sent earlier stop working.
+// Nothing is sent when the last link went out less than `RESET_COOLDOWN_SECS` ago.
+pub async fn send_password_reset_email(
+    state: &AppState,
+    user_id: &Uuid,
+    name: &str,
+    email: &str,
+) -> sqlx::Result<()> {
+    let created_at = Utc::now();
+    let last_sent = last_password_reset_token_at(&state.pool, user_id).await?;
+    if last_sent.is_some_and(|sent| created_at - sent < Duration::seconds(RESET_COOLDOWN_SECS)) {
+        return Ok(());
+    }
+
+    let token = generate_token();
+    let ttl_minutes = state.settings.auth.password_reset_ttl_minutes;
+    let expires_at = created_at + Duration::minutes(ttl_minutes);
+
+    let mut tx = state.pool.begin().await?;
+    insert_password_reset_token(
+        &mut tx,
+        &Uuid::new_v4(),
+        user_id,
+        &hash_token(&token),
+        &created_at,
+        &expires_at,
+    )
+    .await?;
+    tx.commit().await?;
+
+    let link = format!(
+        "{}/reset-password?token={}",
+        state.settings.email.link_base_url.trim_end_matches('/'),
+        token
+    );
+    let ttl_minutes = ttl_minutes.to_string();
+    let message = RESET_PASSWORD.render(
+        email,
+        &[
+            ("name", name),
+            ("link", &link),
+            ("ttl_minutes", &ttl_minutes),
+        ],
+    );
+    send_in_background(state.email.clone(), message);
+
+    Ok(())
+}
+
+// Set a new, already hashed password with a reset token and log the user out everywhere.
+// Returns the user, None when the token is unknown, used or expired.
+pub async fn reset_password(
+    state: &AppState,
+    token: &str,
+    hashed_password: &str,
+) -> sqlx::Result<Option<Uuid>> {
+    let mut tx = state.pool.begin().await?;
+
+    let Some(user_id) = consume_password_reset_token(&mut tx, &hash_token(token)).await? else {
+        return Ok(None);
+    };
+    set_user_password(&mut tx, &user_id, hashed_password).await?;
+    revoke_user_refresh_tokens(&mut tx, &user_id, &Utc::now()).await?;
+
+    tx.commit().await?;
+    Ok(Some(user_id))
+}
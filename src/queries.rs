@@ -1,12 +1,42 @@
+use std::collections::HashMap;
+
 use chrono::{DateTime, Utc};
-use sqlx::{postgres::PgQueryResult, PgPool};
+use sqlx::{postgres::PgQueryResult, PgConnection, PgPool, Postgres, QueryBuilder};
 use uuid::Uuid;
 
-use crate::model::{Post, User, UserResponse};
+use crate::error::AppError;
+use crate::model::{
+    Comment, LoginThrottle, MfaChallenge, Post, PostFilter, PostRevision, PostRevisionSummary,
+    PostSearchResult, PostStatus, PostWithAuthor, ReactionCounts, ReactionKind, RefreshToken,
+    TagCount, TotpState, TrashedPost, User, UserResponse,
+};
+use crate::pagination::Page;
+use crate::search::{SearchTerms, TEXT_SEARCH_CONFIG};
+use crate::slug::{candidate_slug, slugify};
+use crate::tags::TagMatch;
+
+// Titles are unique among the posts an author has outside the trash
+pub const TITLE_TAKEN: &str = "The author already has a post with this title!";
+
+// Columns of the `Post` model as seen by the caller, for queries built at runtime
+fn push_post_columns(query: &mut QueryBuilder<'_, Postgres>, caller_id: &Uuid) {
+    query
+        .push(
+            "posts.id, posts.user_id, posts.title, posts.slug, posts.content, \
+            post_tag_names(posts.id) AS tags, post_comment_count(posts.id) AS comment_count, \
+            posts.comments_locked, post_reactions(posts.id) AS reactions, posts.reaction_count, \
+            post_reacted(posts.id, ",
+        )
+        .push_bind(*caller_id)
+        .push(
+            ") AS reacted, posts.status, posts.published_at, posts.version, posts.created_at, \
+            posts.updated_at",
+        );
+}
 
 //insert user into the database
 pub async fn user_registration(
-    pool: &PgPool,
+    conn: &mut PgConnection,
     id: &Uuid,
     name: &str,
     email: &str,
@@ -24,7 +54,44 @@ pub async fn user_registration(
         email,
         password
     )
-    .fetch_one(pool)
+    .fetch_one(conn)
+    .await
+}
+
+// Grant a role to a user
+pub async fn assign_role(conn: &mut PgConnection, user_id: &Uuid, role: &str) -> sqlx::Result<()> {
+    sqlx::query!(
+        r#"
+            INSERT INTO user_roles(user_id, role)
+            VALUES($1, $2)
+            ON CONFLICT DO NOTHING
+        "#,
+        user_id,
+        role
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+// Names of the roles held by a user
+pub async fn get_user_roles(pool: &PgPool, user_id: &Uuid) -> sqlx::Result<Vec<String>> {
+    sqlx::query_scalar!(
+        "SELECT role FROM user_roles WHERE user_id = $1 ORDER BY role",
+        user_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+// Permissions granted by any of the given roles
+pub async fn get_role_permissions(pool: &PgPool, roles: &[String]) -> sqlx::Result<Vec<String>> {
+    sqlx::query_scalar!(
+        "SELECT DISTINCT permission FROM role_permissions WHERE role = ANY($1)",
+        roles
+    )
+    .fetch_all(pool)
     .await
 }
 
@@ -33,7 +100,7 @@ pub async fn get_user_with_email(pool: &PgPool, email: &str) -> sqlx::Result<Use
     sqlx::query_as!(
         User,
         r#"
-            SELECT id, name, email, password FROM users
+            SELECT id, name, email, password, email_verified_at, totp_enabled_at FROM users
             WHERE email = $1
         "#,
         email
@@ -42,75 +109,1547 @@ pub async fn get_user_with_email(pool: &PgPool, email: &str) -> sqlx::Result<Use
     .await
 }
 
-// get all posts from db
-pub async fn get_posts(pool: &PgPool) -> sqlx::Result<Vec<Post>> {
+// Whether a user verified their email address
+pub async fn is_email_verified(pool: &PgPool, user_id: &Uuid) -> sqlx::Result<bool> {
+    sqlx::query_scalar!(
+        r#"SELECT email_verified_at IS NOT NULL AS "verified!" FROM users WHERE id = $1"#,
+        user_id
+    )
+    .fetch_one(pool)
+    .await
+}
+
+// Posts a caller may read: published ones, scheduled ones whose time has come and their own,
+// never those in the trash. The SQL macros below repeat this condition.
+fn push_visible(query: &mut QueryBuilder<'_, Postgres>, caller_id: &Uuid) {
+    query
+        .push(
+            " AND posts.deleted_at IS NULL \
+            AND (posts.status = 'published' \
+            OR (posts.status = 'scheduled' AND posts.published_at <= NOW()) \
+            OR posts.user_id = ",
+        )
+        .push_bind(*caller_id)
+        .push(")");
+}
+
+fn push_post_filter(query: &mut QueryBuilder<'_, Postgres>, filter: &PostFilter) {
+    if let Some(author) = filter.author {
+        query.push(" AND posts.user_id = ").push_bind(author);
+    }
+    if let Some(status) = filter.status {
+        query.push(" AND posts.status = ").push_bind(status);
+    }
+    if let Some(after) = filter.created_after {
+        query.push(" AND posts.created_at >= ").push_bind(after);
+    }
+    if let Some(before) = filter.created_before {
+        query.push(" AND posts.created_at < ").push_bind(before);
+    }
+    if !filter.tags.is_empty() {
+        // Number of the wanted tags the post carries
+        query
+            .push(
+                " AND (SELECT COUNT(*) FROM post_tags pt JOIN tags t ON t.id = pt.tag_id \
+                WHERE pt.post_id = posts.id AND t.name = ANY(",
+            )
+            .push_bind(filter.tags.clone())
+            .push("))");
+        match filter.tag_match {
+            TagMatch::Any => query.push(" > 0"),
+            TagMatch::All => query.push(" = ").push_bind(filter.tags.len() as i64),
+        };
+    }
+}
+
+// Get one page of the posts visible to the caller matching a filter
+pub async fn get_posts(
+    pool: &PgPool,
+    caller_id: &Uuid,
+    filter: &PostFilter,
+    page: &Page,
+) -> sqlx::Result<Vec<Post>> {
+    let mut query = QueryBuilder::new("SELECT ");
+    push_post_columns(&mut query, caller_id);
+    query.push(" FROM posts WHERE TRUE");
+    push_visible(&mut query, caller_id);
+    push_post_filter(&mut query, filter);
+    page.push_keyset(&mut query, "posts");
+
+    query.build_query_as::<Post>().fetch_all(pool).await
+}
+
+// Get one page of the posts matching a full-text search. Matches are ranked in a subquery
+// so the keyset condition can refer to the rank, headlines are only built for the page.
+// Content is HTML escaped before the matches are marked, so `<mark>` is the only markup.
+pub async fn search_posts(
+    pool: &PgPool,
+    caller_id: &Uuid,
+    terms: &SearchTerms,
+    filter: &PostFilter,
+    page: &Page,
+) -> sqlx::Result<Vec<PostSearchResult>> {
+    let mut query = QueryBuilder::new("SELECT ");
+    push_post_columns(&mut query, caller_id);
+    query.push(format!(
+        ", posts.rank, \
+            ts_headline('{TEXT_SEARCH_CONFIG}', \
+                replace(replace(replace(posts.content, '&', '&amp;'), '<', '&lt;'), '>', '&gt;'), \
+                posts.query, 'StartSel=<mark>, StopSel=</mark>, MaxFragments=2') AS headline \
+        FROM (SELECT posts.*, ts_rank(posts.search, q.query) AS rank, q.query FROM posts, (SELECT "
+    ));
+    terms.push_tsquery(&mut query);
+    query.push(" AS query) q WHERE posts.search @@ q.query");
+    push_visible(&mut query, caller_id);
+    push_post_filter(&mut query, filter);
+    query.push(") AS posts WHERE TRUE");
+    page.push_keyset(&mut query, "posts");
+
+    query
+        .build_query_as::<PostSearchResult>()
+        .fetch_all(pool)
+        .await
+}
+
+// Get a single post visible to the caller
+pub async fn get_post(pool: &PgPool, id: &Uuid, caller_id: &Uuid) -> sqlx::Result<Option<Post>> {
     sqlx::query_as!(
         Post,
         r#"
-            SELECT id, user_id, title, content, created_at, updated_at
+            SELECT id, user_id, title, slug, content, post_tag_names(id) AS "tags!",
+                post_comment_count(id) AS "comment_count!", comments_locked,
+                post_reactions(id) AS "reactions!: ReactionCounts", reaction_count,
+                post_reacted(id, $2) AS "reacted!: Vec<ReactionKind>",
+                status AS "status: PostStatus", published_at, version, created_at, updated_at
             FROM posts
+            WHERE id = $1 AND deleted_at IS NULL
+                AND (status = 'published'
+                    OR (status = 'scheduled' AND published_at <= NOW())
+                    OR user_id = $2)
+        "#,
+        id,
+        caller_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+// Tags of the posts visible to the caller, most used first
+pub async fn get_tags(pool: &PgPool, caller_id: &Uuid) -> sqlx::Result<Vec<TagCount>> {
+    sqlx::query_as!(
+        TagCount,
+        r#"
+            SELECT t.name, COUNT(*) AS "count!"
+            FROM tags t
+            JOIN post_tags pt ON pt.tag_id = t.id
+            JOIN posts p ON p.id = pt.post_id
+            WHERE p.deleted_at IS NULL
+                AND (p.status = 'published'
+                    OR (p.status = 'scheduled' AND p.published_at <= NOW())
+                    OR p.user_id = $1)
+            GROUP BY t.name
+            ORDER BY COUNT(*) DESC, t.name
         "#,
+        caller_id
     )
     .fetch_all(pool)
     .await
 }
 
+// Id and current slug of the post that is or was known by a slug
+pub async fn find_post_slug(pool: &PgPool, slug: &str) -> sqlx::Result<Option<(Uuid, String)>> {
+    let row = sqlx::query!(
+        r#"
+            SELECT p.id, p.slug
+            FROM post_slugs s
+            JOIN posts p ON p.id = s.post_id
+            WHERE s.slug = $1
+        "#,
+        slug
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(|row| (row.id, row.slug)))
+}
+
+// Get a single post visible to the caller together with its author
+pub async fn get_post_with_author(
+    pool: &PgPool,
+    id: &Uuid,
+    caller_id: &Uuid,
+) -> sqlx::Result<Option<PostWithAuthor>> {
+    let row = sqlx::query!(
+        r#"
+            SELECT p.id, p.title, p.slug, p.content, post_tag_names(p.id) AS "tags!",
+                post_comment_count(p.id) AS "comment_count!", p.comments_locked,
+                post_reactions(p.id) AS "reactions!: ReactionCounts", p.reaction_count,
+                post_reacted(p.id, $2) AS "reacted!: Vec<ReactionKind>",
+                p.status AS "status: PostStatus", p.published_at, p.version, p.created_at,
+                p.updated_at,
+                u.id AS author_id, u.name AS author_name, u.email AS author_email
+            FROM posts p
+            JOIN users u ON u.id = p.user_id
+            WHERE p.id = $1 AND p.deleted_at IS NULL
+                AND (p.status = 'published'
+                    OR (p.status = 'scheduled' AND p.published_at <= NOW())
+                    OR p.user_id = $2)
+        "#,
+        id,
+        caller_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(|row| PostWithAuthor {
+        id: row.id,
+        author: UserResponse {
+            id: row.author_id,
+            name: row.author_name,
+            email: row.author_email,
+        },
+        title: row.title,
+        slug: row.slug,
+        content: row.content,
+        tags: row.tags,
+        comment_count: row.comment_count,
+        comments_locked: row.comments_locked,
+        reactions: row.reactions,
+        reaction_count: row.reaction_count,
+        reacted: row.reacted,
+        status: row.status,
+        published_at: row.published_at,
+        version: row.version,
+        created_at: row.created_at,
+        updated_at: row.updated_at,
+    }))
+}
+
 // Insert new created post into the database
+#[allow(clippy::too_many_arguments)]
 pub async fn create_post(
     pool: &PgPool,
     id: &Uuid,
     user_id: &Uuid,
     title: &str,
     content: &str,
+    status: PostStatus,
+    published_at: Option<DateTime<Utc>>,
     created_at: &DateTime<Utc>,
     updated_at: &DateTime<Utc>,
+    tags: &[String],
 ) -> sqlx::Result<Post> {
-    sqlx::query_as!(
+    let mut tx = pool.begin().await?;
+    let slug = claim_slug(&mut tx, id, title).await?;
+
+    let post = sqlx::query_as!(
         Post,
         r#"
-            INSERT INTO posts(id, user_id, title, content, created_at, updated_at)
-            VALUES($1, $2, $3, $4, $5, $6)
-            RETURNING id, user_id, title, content, created_at, updated_at
+            INSERT INTO posts(id, user_id, title, slug, content, status, published_at, created_at, updated_at)
+            VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            RETURNING id, user_id, title, slug, content, post_tag_names(id) AS "tags!",
+                post_comment_count(id) AS "comment_count!", comments_locked,
+                post_reactions(id) AS "reactions!: ReactionCounts", reaction_count,
+                post_reacted(id, $2) AS "reacted!: Vec<ReactionKind>",
+                status AS "status: PostStatus", published_at, version, created_at, updated_at
         "#,
         id,
         user_id,
         title,
+        slug,
         content,
+        status as PostStatus,
+        published_at,
         created_at,
         updated_at
     )
-    .fetch_one(pool)
-    .await
+    .fetch_one(&mut *tx)
+    .await?;
+    record_slug(&mut tx, id, &post.slug).await?;
+    set_post_tags(&mut tx, id, tags).await?;
+
+    tx.commit().await?;
+    Ok(Post {
+        tags: tags.to_vec(),
+        ..post
+    })
+}
+
+// Pick the slug for a post with the given title: the first of base, base-2, base-3... that no
+// other post was ever known by. A post gets back a slug it had before.
+async fn claim_slug(conn: &mut PgConnection, post_id: &Uuid, title: &str) -> sqlx::Result<String> {
+    let base = slugify(title);
+
+    // Posts claiming the same base wait for each other until their transaction ends
+    sqlx::query!("SELECT pg_advisory_xact_lock(hashtext($1))", base)
+        .execute(&mut *conn)
+        .await?;
+    let taken: HashMap<String, Uuid> = sqlx::query!(
+        "SELECT slug, post_id FROM post_slugs WHERE slug = $1 OR slug LIKE $1 || '-%'",
+        base
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .map(|row| (row.slug, row.post_id))
+    .collect();
+
+    Ok((1..)
+        .map(|n| candidate_slug(&base, n))
+        .find(|slug| taken.get(slug).is_none_or(|owner| owner == post_id))
+        .expect("a free slug candidate"))
+}
+
+// Remember a slug of a post, so it keeps resolving after the post moves on to another one
+async fn record_slug(conn: &mut PgConnection, post_id: &Uuid, slug: &str) -> sqlx::Result<()> {
+    sqlx::query!(
+        "INSERT INTO post_slugs(slug, post_id) VALUES($1, $2) ON CONFLICT (slug) DO NOTHING",
+        slug,
+        post_id
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+// Give a post exactly the given normalized tags, creating tags nobody used before
+async fn set_post_tags(
+    conn: &mut PgConnection,
+    post_id: &Uuid,
+    tags: &[String],
+) -> sqlx::Result<()> {
+    let ids: Vec<Uuid> = tags.iter().map(|_| Uuid::new_v4()).collect();
+    sqlx::query!(
+        r#"
+            INSERT INTO tags(id, name)
+            SELECT * FROM UNNEST($1::uuid[], $2::varchar[])
+            ON CONFLICT (name) DO NOTHING
+        "#,
+        &ids,
+        tags
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query!(
+        r#"
+            DELETE FROM post_tags
+            WHERE post_id = $1 AND tag_id NOT IN (SELECT id FROM tags WHERE name = ANY($2))
+        "#,
+        post_id,
+        tags
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query!(
+        r#"
+            INSERT INTO post_tags(post_id, tag_id)
+            SELECT $1, id FROM tags WHERE name = ANY($2)
+            ON CONFLICT DO NOTHING
+        "#,
+        post_id,
+        tags
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
 }
 
-// Update a given existing post
+// Update a given existing post. Only its author may change it unless `any` is set, and only
+// while it is at one of the `expected` versions when given. The previous title and content are
+// kept as a revision whenever they change.
+#[allow(clippy::too_many_arguments)]
 pub async fn update_post(
     pool: &PgPool,
     title: Option<&str>,
     content: Option<&str>,
+    tags: Option<&[String]>,
     id: &Uuid,
-) -> sqlx::Result<Post> {
-    sqlx::query_as!(
+    caller_id: &Uuid,
+    any: bool,
+    expected: Option<&[i64]>,
+) -> Result<Post, AppError> {
+    let mut tx = pool.begin().await?;
+
+    let Some(previous) = lock_post(&mut tx, id, caller_id, any).await? else {
+        return Err(
+            post_access_error(pool, id, caller_id, any, "Post was changed concurrently!").await,
+        );
+    };
+    check_version(previous.version, expected)?;
+
+    let changed = title.is_some_and(|title| title != previous.title)
+        || content.is_some_and(|content| content != previous.content);
+    if changed {
+        insert_post_revision(&mut tx, id, caller_id, &previous.title, &previous.content).await?;
+    }
+
+    if let Some(tags) = tags {
+        set_post_tags(&mut tx, id, tags).await?;
+    }
+
+    // A new title moves the post to a new slug, the old one keeps redirecting
+    let slug = match title {
+        Some(title) if title != previous.title => Some(claim_slug(&mut tx, id, title).await?),
+        _ => None,
+    };
+
+    let post = sqlx::query_as!(
         Post,
         r#"
             UPDATE posts
             SET
                 title = COALESCE($1, title),
-                content = COALESCE($2, content)
-            WHERE id = $3
-            RETURNING id, user_id, title, content, created_at, updated_at
+                slug = COALESCE($2, slug),
+                content = COALESCE($3, content),
+                version = version + 1,
+                updated_at = NOW()
+            WHERE id = $4
+            RETURNING id, user_id, title, slug, content, post_tag_names(id) AS "tags!",
+                post_comment_count(id) AS "comment_count!", comments_locked,
+                post_reactions(id) AS "reactions!: ReactionCounts", reaction_count,
+                post_reacted(id, $5) AS "reacted!: Vec<ReactionKind>",
+                status AS "status: PostStatus", published_at, version, created_at, updated_at
         "#,
         title,
+        slug,
         content,
-        id
+        id,
+        caller_id
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| AppError::conflict_on_unique(e, TITLE_TAKEN))?;
+    record_slug(&mut tx, id, &post.slug).await?;
+
+    tx.commit().await?;
+    Ok(post)
+}
+
+// Post locked for a write by its author, or by anyone when `any` is set
+struct LockedPost {
+    title: String,
+    content: String,
+    version: i64,
+}
+
+async fn lock_post(
+    conn: &mut PgConnection,
+    id: &Uuid,
+    caller_id: &Uuid,
+    any: bool,
+) -> sqlx::Result<Option<LockedPost>> {
+    sqlx::query_as!(
+        LockedPost,
+        r#"
+            SELECT title, content, version FROM posts
+            WHERE id = $1 AND (user_id = $2 OR $3) AND deleted_at IS NULL
+            FOR UPDATE
+        "#,
+        id,
+        caller_id,
+        any
+    )
+    .fetch_optional(conn)
+    .await
+}
+
+// Writes guarded by If-Match only apply to the version the client has seen
+fn check_version(version: i64, expected: Option<&[i64]>) -> Result<(), AppError> {
+    match expected {
+        Some(expected) if !expected.contains(&version) => Err(AppError::PreconditionFailed(
+            "Post has been modified since it was fetched!".to_string(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+// Record the state of a post before an edit as its next revision. The post row must be
+// locked so revision numbers are handed out one at a time.
+async fn insert_post_revision(
+    conn: &mut PgConnection,
+    post_id: &Uuid,
+    editor_id: &Uuid,
+    title: &str,
+    content: &str,
+) -> sqlx::Result<PgQueryResult> {
+    sqlx::query!(
+        r#"
+            INSERT INTO post_revisions(id, post_id, revision, editor_id, title, content)
+            SELECT $1, $2, COALESCE(MAX(revision), 0) + 1, $3, $4, $5
+            FROM post_revisions
+            WHERE post_id = $2
+        "#,
+        Uuid::new_v4(),
+        post_id,
+        editor_id,
+        title,
+        content
+    )
+    .execute(conn)
+    .await
+}
+
+// Revisions of a post, newest first
+pub async fn get_post_revisions(
+    pool: &PgPool,
+    post_id: &Uuid,
+) -> sqlx::Result<Vec<PostRevisionSummary>> {
+    sqlx::query_as!(
+        PostRevisionSummary,
+        r#"
+            SELECT id, revision, editor_id, title, created_at
+            FROM post_revisions
+            WHERE post_id = $1
+            ORDER BY revision DESC
+        "#,
+        post_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+// A single revision of a post
+pub async fn get_post_revision(
+    pool: &PgPool,
+    post_id: &Uuid,
+    revision: i32,
+) -> sqlx::Result<Option<PostRevision>> {
+    sqlx::query_as!(
+        PostRevision,
+        r#"
+            SELECT id, post_id, revision, editor_id, title, content, created_at
+            FROM post_revisions
+            WHERE post_id = $1 AND revision = $2
+        "#,
+        post_id,
+        revision
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+// Current title and content of a post the caller may edit, whatever its status
+pub async fn get_editable_post_text(
+    pool: &PgPool,
+    id: &Uuid,
+    caller_id: &Uuid,
+    any: bool,
+) -> sqlx::Result<Option<(String, String)>> {
+    let row = sqlx::query!(
+        r#"
+            SELECT title, content FROM posts
+            WHERE id = $1 AND (user_id = $2 OR $3) AND deleted_at IS NULL
+        "#,
+        id,
+        caller_id,
+        any
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(|row| (row.title, row.content)))
+}
+
+// Fail unless the caller may edit the post: its author, or anyone when `any` is set
+pub async fn check_post_editable(
+    pool: &PgPool,
+    id: &Uuid,
+    caller_id: &Uuid,
+    any: bool,
+) -> Result<(), AppError> {
+    let editable = sqlx::query_scalar!(
+        r#"SELECT EXISTS(
+            SELECT 1 FROM posts WHERE id = $1 AND (user_id = $2 OR $3) AND deleted_at IS NULL
+        ) AS "editable!""#,
+        id,
+        caller_id,
+        any
     )
     .fetch_one(pool)
+    .await?;
+
+    match editable {
+        true => Ok(()),
+        false => {
+            Err(post_access_error(pool, id, caller_id, any, "Post was changed concurrently!").await)
+        }
+    }
+}
+
+// Move a post to another status. Only posts currently in one of the `from` statuses change,
+// and only their author may change them unless `any` is set.
+pub async fn set_post_status(
+    pool: &PgPool,
+    id: &Uuid,
+    caller_id: &Uuid,
+    any: bool,
+    from: &[PostStatus],
+    to: PostStatus,
+    published_at: Option<DateTime<Utc>>,
+) -> Result<Post, AppError> {
+    let post = sqlx::query_as!(
+        Post,
+        r#"
+            UPDATE posts
+            SET
+                status = $1::post_status,
+                version = version + 1,
+                -- Archiving keeps the original publish time
+                published_at = CASE WHEN $1::post_status = 'archived' THEN published_at ELSE $2 END,
+                updated_at = NOW()
+            WHERE id = $3 AND (user_id = $4 OR $5) AND status = ANY($6) AND deleted_at IS NULL
+            RETURNING id, user_id, title, slug, content, post_tag_names(id) AS "tags!",
+                post_comment_count(id) AS "comment_count!", comments_locked,
+                post_reactions(id) AS "reactions!: ReactionCounts", reaction_count,
+                post_reacted(id, $4) AS "reacted!: Vec<ReactionKind>",
+                status AS "status: PostStatus", published_at, version, created_at, updated_at
+        "#,
+        to as PostStatus,
+        published_at,
+        id,
+        caller_id,
+        any,
+        from as &[PostStatus]
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    match post {
+        Some(post) => Ok(post),
+        None => {
+            let action = match to {
+                PostStatus::Draft => "unpublished",
+                PostStatus::Published | PostStatus::Scheduled => "published",
+                PostStatus::Archived => "archived",
+            };
+            let message = format!("Post cannot be {} in its current status!", action);
+            Err(post_access_error(pool, id, caller_id, any, &message).await)
+        }
+    }
+}
+
+// Scheduled posts whose time has come become published. They are visible from that moment on
+// anyway, this only keeps the stored status honest.
+pub async fn publish_scheduled_posts(pool: &PgPool) -> sqlx::Result<u64> {
+    let result = sqlx::query!(
+        r#"
+            UPDATE posts SET status = 'published', version = version + 1
+            WHERE status = 'scheduled' AND published_at <= NOW() AND deleted_at IS NULL
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+// Move a post with a given id to its author's trash. Only its author may delete it unless `any`
+// is set, and only while it is at one of the `expected` versions when given.
+pub async fn delete_post(
+    pool: &PgPool,
+    id: &Uuid,
+    caller_id: &Uuid,
+    any: bool,
+    expected: Option<&[i64]>,
+) -> Result<(), AppError> {
+    let mut tx = pool.begin().await?;
+
+    let Some(post) = lock_post(&mut tx, id, caller_id, any).await? else {
+        return Err(
+            post_access_error(pool, id, caller_id, any, "Post was changed concurrently!").await,
+        );
+    };
+    check_version(post.version, expected)?;
+
+    sqlx::query!(
+        "UPDATE posts SET deleted_at = NOW(), version = version + 1 WHERE id = $1",
+        id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+// Posts in the caller's trash, most recently deleted first, with the time each is purged at
+pub async fn get_trashed_posts(
+    pool: &PgPool,
+    caller_id: &Uuid,
+    retention_days: i32,
+) -> sqlx::Result<Vec<TrashedPost>> {
+    let mut query = QueryBuilder::new("SELECT ");
+    push_post_columns(&mut query, caller_id);
+    query
+        .push(", posts.deleted_at, posts.deleted_at + make_interval(days => ")
+        .push_bind(retention_days)
+        .push(") AS purge_at FROM posts WHERE posts.deleted_at IS NOT NULL AND posts.user_id = ")
+        .push_bind(*caller_id)
+        .push(" ORDER BY posts.deleted_at DESC, posts.id DESC");
+
+    query.build_query_as::<TrashedPost>().fetch_all(pool).await
+}
+
+// Take a post back out of the trash. Only its author may restore it unless `any` is set.
+pub async fn restore_post(
+    pool: &PgPool,
+    id: &Uuid,
+    caller_id: &Uuid,
+    any: bool,
+) -> Result<Post, AppError> {
+    let post = sqlx::query_as!(
+        Post,
+        r#"
+            UPDATE posts
+            SET deleted_at = NULL, version = version + 1
+            WHERE id = $1 AND (user_id = $2 OR $3) AND deleted_at IS NOT NULL
+            RETURNING id, user_id, title, slug, content, post_tag_names(id) AS "tags!",
+                post_comment_count(id) AS "comment_count!", comments_locked,
+                post_reactions(id) AS "reactions!: ReactionCounts", reaction_count,
+                post_reacted(id, $2) AS "reacted!: Vec<ReactionKind>",
+                status AS "status: PostStatus", published_at, version, created_at, updated_at
+        "#,
+        id,
+        caller_id,
+        any
+    )
+    .fetch_optional(pool)
     .await
+    .map_err(|e| AppError::conflict_on_unique(e, TITLE_TAKEN))?;
+
+    match post {
+        Some(post) => Ok(post),
+        None => Err(post_access_error(pool, id, caller_id, any, "Post is not in the trash!").await),
+    }
 }
 
-// Delete a post with a given id
-pub async fn delete_post(pool: &PgPool, id: &Uuid) -> sqlx::Result<PgQueryResult> {
-    sqlx::query!("DELETE FROM posts where id = $1", id)
-        .execute(pool)
-        .await
+// Permanently remove posts that have been in the trash longer than the retention period
+pub async fn purge_trashed_posts(pool: &PgPool, retention_days: i32) -> sqlx::Result<u64> {
+    let result = sqlx::query!(
+        "DELETE FROM posts WHERE deleted_at <= NOW() - make_interval(days => $1)",
+        retention_days
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+// Why a guarded write touched no row: the post is missing or hidden from the caller, belongs
+// to someone else, or is not in a state that allows the change. Trashed posts count as missing.
+async fn post_access_error(
+    pool: &PgPool,
+    id: &Uuid,
+    caller_id: &Uuid,
+    any: bool,
+    conflict: &str,
+) -> AppError {
+    let post = sqlx::query!(
+        r#"
+            SELECT user_id,
+                (status = 'published' OR (status = 'scheduled' AND published_at <= NOW()))
+                    AS "public!"
+            FROM posts
+            WHERE id = $1 AND deleted_at IS NULL
+        "#,
+        id
+    )
+    .fetch_optional(pool)
+    .await;
+
+    match post {
+        Ok(Some(post)) if post.user_id == *caller_id || any => {
+            AppError::Conflict(conflict.to_string())
+        }
+        Ok(Some(post)) if post.public => {
+            AppError::Forbidden("You are not allowed to modify this post!".to_string())
+        }
+        Ok(_) => AppError::NotFound("Post with given id not found!".to_string()),
+        Err(e) => AppError::Database(e),
+    }
+}
+
+// Lock or unlock the comments of a post. Only its author may do so unless `any` is set.
+pub async fn set_comments_locked(
+    pool: &PgPool,
+    id: &Uuid,
+    caller_id: &Uuid,
+    any: bool,
+    locked: bool,
+) -> Result<Post, AppError> {
+    let post = sqlx::query_as!(
+        Post,
+        r#"
+            UPDATE posts
+            SET comments_locked = $1, version = version + 1, updated_at = NOW()
+            WHERE id = $2 AND (user_id = $3 OR $4) AND deleted_at IS NULL
+            RETURNING id, user_id, title, slug, content, post_tag_names(id) AS "tags!",
+                post_comment_count(id) AS "comment_count!", comments_locked,
+                post_reactions(id) AS "reactions!: ReactionCounts", reaction_count,
+                post_reacted(id, $3) AS "reacted!: Vec<ReactionKind>",
+                status AS "status: PostStatus", published_at, version, created_at, updated_at
+        "#,
+        locked,
+        id,
+        caller_id,
+        any
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    match post {
+        Some(post) => Ok(post),
+        None => {
+            Err(post_access_error(pool, id, caller_id, any, "Post was changed concurrently!").await)
+        }
+    }
+}
+
+// React to a post. Reacting again with the same kind changes nothing.
+pub async fn add_reaction(
+    pool: &PgPool,
+    post_id: &Uuid,
+    user_id: &Uuid,
+    kind: ReactionKind,
+) -> sqlx::Result<PgQueryResult> {
+    sqlx::query!(
+        r#"
+            INSERT INTO post_reactions(post_id, user_id, kind) VALUES($1, $2, $3)
+            ON CONFLICT DO NOTHING
+        "#,
+        post_id,
+        user_id,
+        kind as ReactionKind
+    )
+    .execute(pool)
+    .await
+}
+
+// Take back a reaction. Taking back one that was never given changes nothing.
+pub async fn remove_reaction(
+    pool: &PgPool,
+    post_id: &Uuid,
+    user_id: &Uuid,
+    kind: ReactionKind,
+) -> sqlx::Result<PgQueryResult> {
+    sqlx::query!(
+        "DELETE FROM post_reactions WHERE post_id = $1 AND user_id = $2 AND kind = $3",
+        post_id,
+        user_id,
+        kind as ReactionKind
+    )
+    .execute(pool)
+    .await
+}
+
+// Comments of a post, oldest first
+pub async fn get_comments(pool: &PgPool, post_id: &Uuid) -> sqlx::Result<Vec<Comment>> {
+    sqlx::query_as!(
+        Comment,
+        r#"
+            SELECT id, post_id, user_id, parent_id, depth,
+                CASE WHEN deleted_at IS NULL THEN content END AS content,
+                deleted_at IS NOT NULL AS "deleted!", created_at, updated_at
+            FROM comments
+            WHERE post_id = $1
+            ORDER BY created_at, id
+        "#,
+        post_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+// A single comment, deleted or not
+pub async fn get_comment(pool: &PgPool, id: &Uuid) -> sqlx::Result<Option<Comment>> {
+    sqlx::query_as!(
+        Comment,
+        r#"
+            SELECT id, post_id, user_id, parent_id, depth,
+                CASE WHEN deleted_at IS NULL THEN content END AS content,
+                deleted_at IS NOT NULL AS "deleted!", created_at, updated_at
+            FROM comments
+            WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+// Insert a comment unless the post's comments are locked
+pub async fn create_comment(
+    pool: &PgPool,
+    id: &Uuid,
+    post_id: &Uuid,
+    user_id: &Uuid,
+    parent_id: Option<Uuid>,
+    depth: i32,
+    content: &str,
+) -> sqlx::Result<Option<Comment>> {
+    sqlx::query_as!(
+        Comment,
+        r#"
+            INSERT INTO comments(id, post_id, user_id, parent_id, depth, content)
+            SELECT $1, $2, $3, $4, $5, $6
+            WHERE EXISTS(SELECT 1 FROM posts WHERE id = $2 AND NOT comments_locked)
+            RETURNING id, post_id, user_id, parent_id, depth, content AS "content?",
+                FALSE AS "deleted!", created_at, updated_at
+        "#,
+        id,
+        post_id,
+        user_id,
+        parent_id,
+        depth,
+        content
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+// Change the content of a comment unless the post's comments are locked
+pub async fn update_comment(
+    pool: &PgPool,
+    id: &Uuid,
+    content: &str,
+) -> sqlx::Result<Option<Comment>> {
+    sqlx::query_as!(
+        Comment,
+        r#"
+            UPDATE comments
+            SET content = $1, updated_at = NOW()
+            WHERE id = $2 AND deleted_at IS NULL
+                AND EXISTS(SELECT 1 FROM posts WHERE id = comments.post_id AND NOT comments_locked)
+            RETURNING id, post_id, user_id, parent_id, depth, content AS "content?",
+                FALSE AS "deleted!", created_at, updated_at
+        "#,
+        content,
+        id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+// Delete a comment. Its content is dropped, the row stays so replies keep their parent.
+pub async fn delete_comment(pool: &PgPool, id: &Uuid) -> sqlx::Result<PgQueryResult> {
+    sqlx::query!(
+        "UPDATE comments SET content = '', deleted_at = NOW() WHERE id = $1 AND deleted_at IS NULL",
+        id
+    )
+    .execute(pool)
+    .await
+}
+
+// Persist a newly issued refresh token
+pub async fn insert_refresh_token(
+    conn: &mut PgConnection,
+    id: &Uuid,
+    user_id: &Uuid,
+    family_id: &Uuid,
+    token_hash: &str,
+    created_at: &DateTime<Utc>,
+    expires_at: &DateTime<Utc>,
+) -> sqlx::Result<PgQueryResult> {
+    sqlx::query!(
+        r#"
+            INSERT INTO refresh_tokens(id, user_id, family_id, token_hash, created_at, expires_at)
+            VALUES($1, $2, $3, $4, $5, $6)
+        "#,
+        id,
+        user_id,
+        family_id,
+        token_hash,
+        created_at,
+        expires_at
+    )
+    .execute(conn)
+    .await
+}
+
+// Lock a refresh token by its hash until the surrounding transaction ends
+pub async fn get_refresh_token_for_update(
+    conn: &mut PgConnection,
+    token_hash: &str,
+) -> sqlx::Result<Option<RefreshToken>> {
+    sqlx::query_as!(
+        RefreshToken,
+        r#"
+            SELECT id, user_id, family_id, expires_at, rotated_at, reissued_at, revoked_at
+            FROM refresh_tokens
+            WHERE token_hash = $1
+            FOR UPDATE
+        "#,
+        token_hash
+    )
+    .fetch_optional(conn)
+    .await
+}
+
+// Mark a refresh token as exchanged for a new one
+pub async fn mark_refresh_token_rotated(
+    conn: &mut PgConnection,
+    id: &Uuid,
+    rotated_at: &DateTime<Utc>,
+) -> sqlx::Result<PgQueryResult> {
+    sqlx::query!(
+        "UPDATE refresh_tokens SET rotated_at = $1 WHERE id = $2",
+        rotated_at,
+        id
+    )
+    .execute(conn)
+    .await
+}
+
+// Mark a rotated refresh token as exchanged a second time within the grace window
+pub async fn mark_refresh_token_reissued(
+    conn: &mut PgConnection,
+    id: &Uuid,
+    reissued_at: &DateTime<Utc>,
+) -> sqlx::Result<PgQueryResult> {
+    sqlx::query!(
+        "UPDATE refresh_tokens SET reissued_at = $1 WHERE id = $2",
+        reissued_at,
+        id
+    )
+    .execute(conn)
+    .await
+}
+
+// Revoke every token descending from the same login
+pub async fn revoke_refresh_token_family(
+    conn: &mut PgConnection,
+    family_id: &Uuid,
+    revoked_at: &DateTime<Utc>,
+) -> sqlx::Result<PgQueryResult> {
+    sqlx::query!(
+        r#"
+            UPDATE refresh_tokens
+            SET revoked_at = $1
+            WHERE family_id = $2 AND revoked_at IS NULL
+        "#,
+        revoked_at,
+        family_id
+    )
+    .execute(conn)
+    .await
+}
+
+// Persist a newly issued email verification token, dropping the user's unused ones
+pub async fn insert_verification_token(
+    conn: &mut PgConnection,
+    id: &Uuid,
+    user_id: &Uuid,
+    token_hash: &str,
+    created_at: &DateTime<Utc>,
+    expires_at: &DateTime<Utc>,
+) -> sqlx::Result<PgQueryResult> {
+    sqlx::query!(
+        "DELETE FROM email_verification_tokens WHERE user_id = $1 AND used_at IS NULL",
+        user_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query!(
+        r#"
+            INSERT INTO email_verification_tokens(id, user_id, token_hash, created_at, expires_at)
+            VALUES($1, $2, $3, $4, $5)
+        "#,
+        id,
+        user_id,
+        token_hash,
+        created_at,
+        expires_at
+    )
+    .execute(conn)
+    .await
+}
+
+// When the last verification token of a user was issued
+pub async fn last_verification_token_at(
+    pool: &PgPool,
+    user_id: &Uuid,
+) -> sqlx::Result<Option<DateTime<Utc>>> {
+    sqlx::query_scalar!(
+        "SELECT MAX(created_at) FROM email_verification_tokens WHERE user_id = $1",
+        user_id
+    )
+    .fetch_one(pool)
+    .await
+}
+
+// Use up an unexpired verification token and mark its user's email address verified.
+// Returns the user, None when the token is unknown, used or expired.
+pub async fn consume_verification_token(
+    pool: &PgPool,
+    token_hash: &str,
+) -> sqlx::Result<Option<Uuid>> {
+    let mut tx = pool.begin().await?;
+
+    let user_id = sqlx::query_scalar!(
+        r#"
+            UPDATE email_verification_tokens
+            SET used_at = NOW()
+            WHERE token_hash = $1 AND used_at IS NULL AND expires_at > NOW()
+            RETURNING user_id
+        "#,
+        token_hash
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    if let Some(user_id) = user_id {
+        sqlx::query!(
+            "UPDATE users SET email_verified_at = COALESCE(email_verified_at, NOW()) WHERE id = $1",
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+    Ok(user_id)
+}
+
+// Persist a newly issued password reset token, dropping the user's unused ones
+pub async fn insert_password_reset_token(
+    conn: &mut PgConnection,
+    id: &Uuid,
+    user_id: &Uuid,
+    token_hash: &str,
+    created_at: &DateTime<Utc>,
+    expires_at: &DateTime<Utc>,
+) -> sqlx::Result<PgQueryResult> {
+    sqlx::query!(
+        "DELETE FROM password_reset_tokens WHERE user_id = $1 AND used_at IS NULL",
+        user_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query!(
+        r#"
+            INSERT INTO password_reset_tokens(id, user_id, token_hash, created_at, expires_at)
+            VALUES($1, $2, $3, $4, $5)
+        "#,
+        id,
+        user_id,
+        token_hash,
+        created_at,
+        expires_at
+    )
+    .execute(conn)
+    .await
+}
+
+// When the last password reset token of a user was issued
+pub async fn last_password_reset_token_at(
+    pool: &PgPool,
+    user_id: &Uuid,
+) -> sqlx::Result<Option<DateTime<Utc>>> {
+    sqlx::query_scalar!(
+        "SELECT MAX(created_at) FROM password_reset_tokens WHERE user_id = $1",
+        user_id
+    )
+    .fetch_one(pool)
+    .await
+}
+
+// Use up an unexpired password reset token, returning its user. None when the token is
+// unknown, used or expired.
+pub async fn consume_password_reset_token(
+    conn: &mut PgConnection,
+    token_hash: &str,
+) -> sqlx::Result<Option<Uuid>> {
+    sqlx::query_scalar!(
+        r#"
+            UPDATE password_reset_tokens
+            SET used_at = NOW()
+            WHERE token_hash = $1 AND used_at IS NULL AND expires_at > NOW()
+            RETURNING user_id
+        "#,
+        token_hash
+    )
+    .fetch_optional(conn)
+    .await
+}
+
+// Replace a user's password. Receiving the reset email proves the address is theirs.
+pub async fn set_user_password(
+    conn: &mut PgConnection,
+    user_id: &Uuid,
+    password: &str,
+) -> sqlx::Result<PgQueryResult> {
+    sqlx::query!(
+        r#"
+            UPDATE users
+            SET password = $1, email_verified_at = COALESCE(email_verified_at, NOW())
+            WHERE id = $2
+        "#,
+        password,
+        user_id
+    )
+    .execute(conn)
+    .await
+}
+
+// Revoke every refresh token of a user, ending all their sessions
+pub async fn revoke_user_refresh_tokens(
+    conn: &mut PgConnection,
+    user_id: &Uuid,
+    revoked_at: &DateTime<Utc>,
+) -> sqlx::Result<PgQueryResult> {
+    sqlx::query!(
+        r#"
+            UPDATE refresh_tokens
+            SET revoked_at = $1
+            WHERE user_id = $2 AND revoked_at IS NULL
+        "#,
+        revoked_at,
+        user_id
+    )
+    .execute(conn)
+    .await
+}
+
+// Query user by id
+pub async fn get_user(pool: &PgPool, id: &Uuid) -> sqlx::Result<User> {
+    sqlx::query_as!(
+        User,
+        r#"
+            SELECT id, name, email, password, email_verified_at, totp_enabled_at FROM users
+            WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_one(pool)
+    .await
+}
+
+// Two-factor authentication state of a user
+pub async fn get_totp_state(conn: &mut PgConnection, user_id: &Uuid) -> sqlx::Result<TotpState> {
+    sqlx::query_as!(
+        TotpState,
+        r#"
+            SELECT totp_secret AS secret, totp_enabled_at AS enabled_at, totp_last_step AS last_step
+            FROM users
+            WHERE id = $1
+        "#,
+        user_id
+    )
+    .fetch_one(conn)
+    .await
+}
+
+// Start or restart enrolling an authenticator. Returns false when one is enabled already.
+pub async fn set_pending_totp_secret(
+    pool: &PgPool,
+    user_id: &Uuid,
+    secret: &[u8],
+) -> sqlx::Result<bool> {
+    let result = sqlx::query!(
+        r#"
+            UPDATE users
+            SET totp_secret = $1, totp_last_step = NULL
+            WHERE id = $2 AND totp_enabled_at IS NULL
+        "#,
+        secret,
+        user_id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+// Enable the pending authenticator of a user, its first code having used `step`
+pub async fn enable_totp(conn: &mut PgConnection, user_id: &Uuid, step: i64) -> sqlx::Result<bool> {
+    let result = sqlx::query!(
+        r#"
+            UPDATE users
+            SET totp_enabled_at = NOW(), totp_last_step = $1
+            WHERE id = $2 AND totp_secret IS NOT NULL AND totp_enabled_at IS NULL
+        "#,
+        step,
+        user_id
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+// Remember the time step of a used code. Returns false when it or a later one was used before.
+pub async fn record_totp_step(
+    conn: &mut PgConnection,
+    user_id: &Uuid,
+    step: i64,
+) -> sqlx::Result<bool> {
+    let result = sqlx::query!(
+        r#"
+            UPDATE users
+            SET totp_last_step = $1
+            WHERE id = $2 AND (totp_last_step IS NULL OR totp_last_step < $1)
+        "#,
+        step,
+        user_id
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+// Turn two-factor authentication off and drop the recovery codes
+pub async fn disable_totp(conn: &mut PgConnection, user_id: &Uuid) -> sqlx::Result<()> {
+    sqlx::query!(
+        r#"
+            UPDATE users
+            SET totp_secret = NULL, totp_enabled_at = NULL, totp_last_step = NULL
+            WHERE id = $1
+        "#,
+        user_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query!("DELETE FROM recovery_codes WHERE user_id = $1", user_id)
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
+
+// Replace the recovery codes of a user with new ones, given by their hashes
+pub async fn replace_recovery_codes(
+    conn: &mut PgConnection,
+    user_id: &Uuid,
+    code_hashes: &[String],
+) -> sqlx::Result<()> {
+    sqlx::query!("DELETE FROM recovery_codes WHERE user_id = $1", user_id)
+        .execute(&mut *conn)
+        .await?;
+
+    let ids: Vec<Uuid> = code_hashes.iter().map(|_| Uuid::new_v4()).collect();
+    sqlx::query!(
+        r#"
+            INSERT INTO recovery_codes(id, user_id, code_hash)
+            SELECT id, $2, code_hash FROM UNNEST($1::UUID[], $3::VARCHAR[]) AS codes(id, code_hash)
+        "#,
+        &ids,
+        user_id,
+        code_hashes
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+// Use up one of a user's recovery codes. Returns false when it is unknown or was used.
+pub async fn consume_recovery_code(
+    conn: &mut PgConnection,
+    user_id: &Uuid,
+    code_hash: &str,
+) -> sqlx::Result<bool> {
+    let result = sqlx::query!(
+        r#"
+            UPDATE recovery_codes
+            SET used_at = NOW()
+            WHERE id = (
+                SELECT id FROM recovery_codes
+                WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+                LIMIT 1
+            )
+        "#,
+        user_id,
+        code_hash
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+// Persist a login waiting for its second factor
+pub async fn insert_mfa_challenge(
+    pool: &PgPool,
+    id: &Uuid,
+    user_id: &Uuid,
+    token_hash: &str,
+    created_at: &DateTime<Utc>,
+    expires_at: &DateTime<Utc>,
+) -> sqlx::Result<PgQueryResult> {
+    sqlx::query!(
+        r#"
+            INSERT INTO mfa_challenges(id, user_id, token_hash, created_at, expires_at)
+            VALUES($1, $2, $3, $4, $5)
+        "#,
+        id,
+        user_id,
+        token_hash,
+        created_at,
+        expires_at
+    )
+    .execute(pool)
+    .await
+}
+
+// Pending login by the hash of its token
+pub async fn get_mfa_challenge(
+    pool: &PgPool,
+    token_hash: &str,
+) -> sqlx::Result<Option<MfaChallenge>> {
+    sqlx::query_as!(
+        MfaChallenge,
+        r#"
+            SELECT id, user_id, expires_at, attempts, used_at
+            FROM mfa_challenges
+            WHERE token_hash = $1
+        "#,
+        token_hash
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+// Lock a pending login by the hash of its token until the surrounding transaction ends
+pub async fn get_mfa_challenge_for_update(
+    conn: &mut PgConnection,
+    token_hash: &str,
+) -> sqlx::Result<Option<MfaChallenge>> {
+    sqlx::query_as!(
+        MfaChallenge,
+        r#"
+            SELECT id, user_id, expires_at, attempts, used_at
+            FROM mfa_challenges
+            WHERE token_hash = $1
+            FOR UPDATE
+        "#,
+        token_hash
+    )
+    .fetch_optional(conn)
+    .await
+}
+
+// Count a wrong code against a pending login, using it up once `used` is set
+pub async fn record_mfa_attempt(
+    conn: &mut PgConnection,
+    id: &Uuid,
+    used: bool,
+) -> sqlx::Result<PgQueryResult> {
+    sqlx::query!(
+        r#"
+            UPDATE mfa_challenges
+            SET attempts = attempts + 1, used_at = CASE WHEN $2 THEN NOW() END
+            WHERE id = $1
+        "#,
+        id,
+        used
+    )
+    .execute(conn)
+    .await
+}
+
+// Mark a pending login as completed
+pub async fn mark_mfa_challenge_used(
+    conn: &mut PgConnection,
+    id: &Uuid,
+) -> sqlx::Result<PgQueryResult> {
+    sqlx::query!(
+        "UPDATE mfa_challenges SET used_at = NOW() WHERE id = $1",
+        id
+    )
+    .execute(conn)
+    .await
+}
+
+// Failed logins recorded for an account or client address
+pub async fn get_login_throttle(
+    pool: &PgPool,
+    scope: &str,
+    key: &str,
+) -> sqlx::Result<Option<LoginThrottle>> {
+    sqlx::query_as!(
+        LoginThrottle,
+        r#"
+            SELECT failures, last_failed_at, locked_until
+            FROM login_throttles
+            WHERE scope = $1 AND key = $2
+        "#,
+        scope,
+        key
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+// Lock the failed logins of an account or client address until the surrounding transaction
+// ends, starting them at zero
+pub async fn get_login_throttle_for_update(
+    conn: &mut PgConnection,
+    scope: &str,
+    key: &str,
+) -> sqlx::Result<LoginThrottle> {
+    sqlx::query!(
+        "INSERT INTO login_throttles(scope, key) VALUES($1, $2) ON CONFLICT DO NOTHING",
+        scope,
+        key
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query_as!(
+        LoginThrottle,
+        r#"
+            SELECT failures, last_failed_at, locked_until
+            FROM login_throttles
+            WHERE scope = $1 AND key = $2
+            FOR UPDATE
+        "#,
+        scope,
+        key
+    )
+    .fetch_one(conn)
+    .await
+}
+
+// Store the failed logins of an account or client address
+pub async fn update_login_throttle(
+    conn: &mut PgConnection,
+    scope: &str,
+    key: &str,
+    throttle: &LoginThrottle,
+) -> sqlx::Result<PgQueryResult> {
+    sqlx::query!(
+        r#"
+            UPDATE login_throttles
+            SET failures = $1, last_failed_at = $2, locked_until = $3
+            WHERE scope = $4 AND key = $5
+        "#,
+        throttle.failures,
+        throttle.last_failed_at,
+        throttle.locked_until,
+        scope,
+        key
+    )
+    .execute(conn)
+    .await
+}
+
+// Forget the failed logins of an account or client address, lifting any lock
+pub async fn clear_login_throttle(
+    pool: &PgPool,
+    scope: &str,
+    key: &str,
+) -> sqlx::Result<PgQueryResult> {
+    sqlx::query!(
+        "DELETE FROM login_throttles WHERE scope = $1 AND key = $2",
+        scope,
+        key
+    )
+    .execute(pool)
+    .await
 }
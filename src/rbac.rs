@@ -0,0 +1,74 @@
+use std::{collections::HashSet, future::Future, pin::Pin};
+
+use actix_web::{dev::Payload, web, FromRequest, HttpMessage, HttpRequest};
+
+use crate::{error::AppError, model::Claim, queries::get_role_permissions, AppState};
+
+// Role given to every newly registered account
+pub const DEFAULT_ROLE: &str = "author";
+
+// Permission names seeded by the roles migration
+pub const POSTS_CREATE: &str = "posts:create";
+pub const POSTS_UPDATE_OWN: &str = "posts:update:own";
+pub const POSTS_UPDATE_ANY: &str = "posts:update:any";
+pub const POSTS_DELETE_OWN: &str = "posts:delete:own";
+pub const POSTS_DELETE_ANY: &str = "posts:delete:any";
+pub const COMMENTS_CREATE: &str = "comments:create";
+pub const COMMENTS_MODERATE: &str = "comments:moderate";
+pub const USERS_MANAGE: &str = "users:manage";
+
+// Permissions granted to the caller through the roles in their access token. They are looked up
+// on every request so changes to role_permissions apply without reissuing tokens.
+//
+// Routes behind `jwt_middleware` declare what they need by extracting this and calling
+// `require`, e.g. `permissions.require(POSTS_CREATE)?`.
+#[derive(Debug, Clone)]
+pub struct Permissions(HashSet<String>);
+
+impl Permissions {
+    pub fn has(&self, permission: &str) -> bool {
+        self.0.contains(permission)
+    }
+
+    // Whether the caller holds `any`. Without it they need at least `own`.
+    pub fn scope(&self, own: &str, any: &str) -> Result<bool, AppError> {
+        if self.has(any) {
+            return Ok(true);
+        }
+        self.require(own)?;
+        Ok(false)
+    }
+
+    pub fn require(&self, permission: &str) -> Result<(), AppError> {
+        if self.has(permission) {
+            Ok(())
+        } else {
+            Err(AppError::Forbidden(format!(
+                "Missing permission `{}`",
+                permission
+            )))
+        }
+    }
+}
+
+impl FromRequest for Permissions {
+    type Error = AppError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let claim = req.extensions().get::<Claim>().cloned();
+        let state = req.app_data::<web::Data<AppState>>().cloned();
+
+        Box::pin(async move {
+            let claim = claim.ok_or_else(|| {
+                AppError::Unauthorized("token_missing", "Missing access token".to_string())
+            })?;
+            let state = state.ok_or_else(|| {
+                AppError::Internal("application state is not configured".to_string())
+            })?;
+
+            let permissions = get_role_permissions(&state.pool, &claim.roles).await?;
+            Ok(Permissions(permissions.into_iter().collect()))
+        })
+    }
+}
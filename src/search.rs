@@ -0,0 +1,125 @@
+use sqlx::{Postgres, QueryBuilder};
+
+use crate::error::AppError;
+
+// Text search configuration used by the posts.search column
+pub const TEXT_SEARCH_CONFIG: &str = "english";
+
+// One part of a search query. All parts must match.
+#[derive(Debug, PartialEq)]
+enum Term {
+    // A plain word, stemmed
+    Word(String),
+    // "quoted words" that must appear next to each other
+    Phrase(String),
+    // word* matching every lexeme starting with it
+    Prefix(String),
+}
+
+// Parsed `q` of a post search
+#[derive(Debug)]
+pub struct SearchTerms(Vec<Term>);
+
+impl SearchTerms {
+    pub fn parse(q: &str) -> Result<SearchTerms, AppError> {
+        let mut terms = Vec::new();
+        let mut rest = q;
+
+        while let Some(start) = rest.find('"') {
+            push_words(&mut terms, &rest[..start]);
+            let quoted = &rest[start + 1..];
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            if !quoted[..end].trim().is_empty() {
+                terms.push(Term::Phrase(quoted[..end].trim().to_string()));
+            }
+            rest = quoted.get(end + 1..).unwrap_or("");
+        }
+        push_words(&mut terms, rest);
+
+        if terms.is_empty() {
+            return Err(AppError::validation(
+                "q",
+                "must contain at least one search term",
+            ));
+        }
+        Ok(SearchTerms(terms))
+    }
+
+    // Append the tsquery matching every term
+    pub fn push_tsquery(&self, query: &mut QueryBuilder<'_, Postgres>) {
+        for (i, term) in self.0.iter().enumerate() {
+            if i > 0 {
+                query.push(" && ");
+            }
+            let (function, value) = match term {
+                Term::Word(word) => ("plainto_tsquery", word.clone()),
+                Term::Phrase(phrase) => ("phraseto_tsquery", phrase.clone()),
+                Term::Prefix(prefix) => ("to_tsquery", format!("{}:*", prefix)),
+            };
+            query
+                .push(format!("{}('{}', ", function, TEXT_SEARCH_CONFIG))
+                .push_bind(value)
+                .push(")");
+        }
+    }
+}
+
+fn push_words(terms: &mut Vec<Term>, text: &str) {
+    for word in text.split_whitespace() {
+        match word.strip_suffix('*') {
+            // Only letters and digits reach to_tsquery, its operators would be a syntax error
+            Some(prefix) => {
+                let prefix: String = prefix.chars().filter(|c| c.is_alphanumeric()).collect();
+                if !prefix.is_empty() {
+                    terms.push(Term::Prefix(prefix));
+                }
+            }
+            None => terms.push(Term::Word(word.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(q: &str) -> Vec<Term> {
+        SearchTerms::parse(q).unwrap().0
+    }
+
+    #[test]
+    fn parses_words_phrases_and_prefixes() {
+        assert_eq!(
+            parse(r#"rust "web api" async*"#),
+            vec![
+                Term::Word("rust".to_string()),
+                Term::Phrase("web api".to_string()),
+                Term::Prefix("async".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn unterminated_quote_runs_to_the_end() {
+        assert_eq!(
+            parse(r#"rust "web api"#),
+            vec![
+                Term::Word("rust".to_string()),
+                Term::Phrase("web api".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn prefixes_keep_only_letters_and_digits() {
+        assert_eq!(parse("c++*"), vec![Term::Prefix("c".to_string())]);
+        assert_eq!(parse("héllo!*"), vec![Term::Prefix("héllo".to_string())]);
+    }
+
+    #[test]
+    fn rejects_queries_without_terms() {
+        for q in ["", "   ", r#""  ""#, "*", "!&*"] {
+            assert!(SearchTerms::parse(q).is_err(), "{:?}", q);
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    model::RefreshToken,
+    queries::{
+        get_refresh_token_for_update, insert_refresh_token, mark_refresh_token_reissued,
+        mark_refresh_token_rotated, revoke_refresh_token_family,
+    },
+    utils::{generate_token, hash_token},
+};
+
+// Why a refresh token could not be exchanged
+#[derive(Debug)]
+pub enum RefreshError {
+    Invalid,
+    Reused,
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for RefreshError {
+    fn from(e: sqlx::Error) -> Self {
+        RefreshError::Database(e)
+    }
+}
+
+// Refresh token handed to the client
+pub struct IssuedRefreshToken {
+    pub user_id: Uuid,
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+// Start a new token family on login
+pub async fn issue_refresh_token(
+    pool: &PgPool,
+    user_id: &Uuid,
+    ttl_days: i64,
+) -> sqlx::Result<IssuedRefreshToken> {
+    let mut conn = pool.acquire().await?;
+    insert_into_family(&mut conn, user_id, &Uuid::new_v4(), ttl_days).await
+}
+
+// What presenting a stored refresh token leads to
+#[derive(Debug, PartialEq)]
+enum Exchange {
+    Invalid,
+    Rotate,
+    Reissue,
+    Revoke,
+}
+
+// Presenting a token that was already exchanged means it leaked, so the whole family is
+// revoked. Within `reuse_grace_secs` of the exchange it is most likely a concurrent or
+// retried request instead, which gets one more token in the family. Only hashes are stored,
+// so the successor issued first can't be handed out again. A second replay is never a race.
+fn exchange(stored: &RefreshToken, now: DateTime<Utc>, reuse_grace_secs: i64) -> Exchange {
+    if stored.revoked_at.is_some() || stored.expires_at <= now {
+        return Exchange::Invalid;
+    }
+
+    match stored.rotated_at {
+        None => Exchange::Rotate,
+        Some(rotated_at)
+            if stored.reissued_at.is_none()
+                && now - rotated_at <= Duration::seconds(reuse_grace_secs) =>
+        {
+            Exchange::Reissue
+        }
+        Some(_) => Exchange::Revoke,
+    }
+}
+
+// Exchange a refresh token for a new one in the same family
+pub async fn rotate_refresh_token(
+    pool: &PgPool,
+    token: &str,
+    ttl_days: i64,
+    reuse_grace_secs: i64,
+) -> Result<IssuedRefreshToken, RefreshError> {
+    let now = Utc::now();
+    let mut tx = pool.begin().await?;
+
+    let Some(stored) = get_refresh_token_for_update(&mut tx, &hash_token(token)).await? else {
+        return Err(RefreshError::Invalid);
+    };
+
+    match exchange(&stored, now, reuse_grace_secs) {
+        Exchange::Invalid => return Err(RefreshError::Invalid),
+        Exchange::Rotate => mark_refresh_token_rotated(&mut tx, &stored.id, &now).await?,
+        Exchange::Reissue => mark_refresh_token_reissued(&mut tx, &stored.id, &now).await?,
+        Exchange::Revoke => {
+            revoke_refresh_token_family(&mut tx, &stored.family_id, &now).await?;
+            tx.commit().await?;
+            return Err(RefreshError::Reused);
+        }
+    };
+
+    let issued = insert_into_family(&mut tx, &stored.user_id, &stored.family_id, ttl_days).await?;
+    tx.commit().await?;
+
+    Ok(issued)
+}
+
+// Revoke the family of a refresh token, unknown tokens are ignored
+pub async fn revoke_refresh_token(pool: &PgPool, token: &str) -> sqlx::Result<()> {
+    let mut tx = pool.begin().await?;
+
+    if let Some(stored) = get_refresh_token_for_update(&mut tx, &hash_token(token)).await? {
+        revoke_refresh_token_family(&mut tx, &stored.family_id, &Utc::now()).await?;
+    }
+
+    tx.commit().await
+}
+
+async fn insert_into_family(
+    conn: &mut PgConnection,
+    user_id: &Uuid,
+    family_id: &Uuid,
+    ttl_days: i64,
+) -> sqlx::Result<IssuedRefreshToken> {
+    let token = generate_token();
+    let created_at = Utc::now();
+    let expires_at = created_at + Duration::days(ttl_days);
+
+    insert_refresh_token(
+        conn,
+        &Uuid::new_v4(),
+        user_id,
+        family_id,
+        &hash_token(&token),
+        &created_at,
+        &expires_at,
+    )
+    .await?;
+
+    Ok(IssuedRefreshToken {
+        user_id: *user_id,
+        token,
+        expires_at,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(now: DateTime<Utc>) -> RefreshToken {
+        RefreshToken {
+            id: Uuid::new_v4(),
+            user_id: Uuid::new_v4(),
+            family_id: Uuid::new_v4(),
+            expires_at: now + Duration::days(30),
+            rotated_at: None,
+            reissued_at: None,
+            revoked_at: None,
+        }
+    }
+
+    #[test]
+    fn unused_tokens_rotate() {
+        let now = Utc::now();
+        assert_eq!(exchange(&token(now), now, 10), Exchange::Rotate);
+    }
+
+    #[test]
+    fn revoked_and_expired_tokens_are_invalid() {
+        let now = Utc::now();
+
+        let mut revoked = token(now);
+        revoked.revoked_at = Some(now - Duration::seconds(1));
+        assert_eq!(exchange(&revoked, now, 10), Exchange::Invalid);
+
+        let mut expired = token(now);
+        expired.expires_at = now;
+        assert_eq!(exchange(&expired, now, 10), Exchange::Invalid);
+    }
+
+    #[test]
+    fn a_replay_inside_the_grace_window_is_reissued_once() {
+        let now = Utc::now();
+        let mut stored = token(now);
+        stored.rotated_at = Some(now - Duration::seconds(10));
+        assert_eq!(exchange(&stored, now, 10), Exchange::Reissue);
+
+        // A second replay, still inside the window
+        stored.reissued_at = Some(now);
+        assert_eq!(
+            exchange(&stored, now + Duration::seconds(1), 10),
+            Exchange::Revoke
+        );
+    }
+
+    #[test]
+    fn a_replay_after_the_grace_window_revokes() {
+        let now = Utc::now();
+        let mut stored = token(now);
+        stored.rotated_at = Some(now - Duration::seconds(11));
+        assert_eq!(exchange(&stored, now, 10), Exchange::Revoke);
+        assert_eq!(exchange(&stored, now, 0), Exchange::Revoke);
+    }
+}
@@ -0,0 +1,890 @@
+use std::{
+    collections::BTreeMap,
+    env, fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use jsonwebtoken::Algorithm;
+use lettre::message::Mailbox;
+
+use crate::keys::SUPPORTED_ALGORITHMS;
+
+// Prefix for environment variables, e.g. BLOG_SERVER__PORT=8080
+const ENV_PREFIX: &str = "BLOG_";
+const ENV_CONFIG_FILE: &str = "BLOG_CONFIG";
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+// Application settings
+#[derive(Clone)]
+pub struct Settings {
+    pub server: ServerSettings,
+    pub database: DatabaseSettings,
+    pub cors: CorsSettings,
+    pub jwt: JwtSettings,
+    pub auth: AuthSettings,
+    pub posts: PostsSettings,
+    pub email: EmailSettings,
+}
+
+#[derive(Clone)]
+pub struct ServerSettings {
+    pub host: String,
+    pub port: u16,
+    pub trust_forwarded_for: bool,
+}
+
+#[derive(Clone)]
+pub struct DatabaseSettings {
+    pub url: String,
+    pub name: String,
+    pub max_connections: u32,
+}
+
+impl DatabaseSettings {
+    // Connection url of the application database
+    pub fn connection_url(&self) -> String {
+        format!("{}/{}", self.url.trim_end_matches('/'), self.name)
+    }
+}
+
+#[derive(Clone)]
+pub struct CorsSettings {
+    pub allowed_origins: Vec<String>,
+}
+
+#[derive(Clone)]
+pub struct JwtSettings {
+    pub algorithm: Algorithm,
+    pub key_id: String,
+    pub secret: String,
+    pub private_key_file: Option<PathBuf>,
+    pub key_ring_dir: Option<PathBuf>,
+    pub key_grace_period_days: i64,
+    pub key_reload_interval_secs: u64,
+    pub access_token_ttl_secs: i64,
+    pub refresh_token_ttl_days: i64,
+    pub refresh_token_reuse_grace_secs: i64,
+}
+
+#[derive(Clone)]
+pub struct AuthSettings {
+    pub token_transport: TokenTransport,
+    pub unverified_access: UnverifiedAccess,
+    pub email_verification_ttl_hours: i64,
+    pub password_reset_ttl_minutes: i64,
+    pub totp_issuer: String,
+    pub mfa_token_ttl_secs: i64,
+    pub max_failed_logins: i32,
+    pub max_failed_logins_per_ip: i32,
+    pub login_backoff_base_secs: i64,
+    pub lockout_secs: i64,
+}
+
+#[derive(Clone)]
+pub struct PostsSettings {
+    pub publish_interval_secs: u64,
+    pub trash_retention_days: i32,
+    pub purge_interval_secs: u64,
+}
+
+#[derive(Clone)]
+pub struct EmailSettings {
+    pub transport: Option<EmailTransport>,
+    pub from: Mailbox,
+    pub link_base_url: String,
+    pub smtp_host: String,
+    pub smtp_port: u16,
+    pub smtp_security: SmtpSecurity,
+    pub smtp_username: Option<String>,
+    pub smtp_password: Option<String>,
+    pub file_dir: PathBuf,
+}
+
+// How outgoing mail is delivered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmailTransport {
+    // Only recipient and subject are written to the log, for development
+    Log,
+    // Sent through an SMTP server
+    Smtp,
+    // Written as .eml files into `file_dir`
+    File,
+}
+
+impl FromStr for EmailTransport {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "log" => Ok(EmailTransport::Log),
+            "smtp" => Ok(EmailTransport::Smtp),
+            "file" => Ok(EmailTransport::File),
+            _ => Err("expected one of log, smtp or file".to_string()),
+        }
+    }
+}
+
+// Encryption of the connection to the SMTP server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmtpSecurity {
+    // Plain text, only for local test servers
+    None,
+    // Upgraded with STARTTLS, usually on port 587
+    StartTls,
+    // TLS from the start, usually on port 465
+    Tls,
+}
+
+impl FromStr for SmtpSecurity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(SmtpSecurity::None),
+            "starttls" => Ok(SmtpSecurity::StartTls),
+            "tls" => Ok(SmtpSecurity::Tls),
+            _ => Err("expected one of none, starttls or tls".to_string()),
+        }
+    }
+}
+
+// What accounts may do before their email address is verified
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnverifiedAccess {
+    // Everything their roles allow
+    Full,
+    // Log in and read, but not change anything
+    Read,
+    // Not even log in
+    None,
+}
+
+impl FromStr for UnverifiedAccess {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "full" => Ok(UnverifiedAccess::Full),
+            "read" => Ok(UnverifiedAccess::Read),
+            "none" => Ok(UnverifiedAccess::None),
+            _ => Err("expected one of full, read or none".to_string()),
+        }
+    }
+}
+
+// Where the access token is accepted from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenTransport {
+    Cookie,
+    Header,
+    Either,
+}
+
+impl FromStr for TokenTransport {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cookie" => Ok(TokenTransport::Cookie),
+            "header" => Ok(TokenTransport::Header),
+            "either" => Ok(TokenTransport::Either),
+            _ => Err("expected one of cookie, header or either".to_string()),
+        }
+    }
+}
+
+impl Settings {
+    // Load settings from config file, environment and command line, in increasing precedence
+    pub fn load(cli: &CommandLine) -> Result<Settings, SettingsError> {
+        let mut problems = Vec::new();
+
+        let file = match config_file(cli) {
+            Ok(Some(path)) => match read_config_file(&path) {
+                Ok(file_values) => Some((format!("file {}", path.display()), file_values)),
+                Err(e) => {
+                    problems.push(e);
+                    None
+                }
+            },
+            Ok(None) => None,
+            Err(e) => {
+                problems.push(e);
+                None
+            }
+        };
+
+        Settings::from_layers(file, env_values(env::vars()), cli, problems)
+    }
+
+    // Settings from the values of each layer, later layers overriding earlier ones
+    fn from_layers(
+        file: Option<(String, Vec<(String, String)>)>,
+        env: Vec<(String, String)>,
+        cli: &CommandLine,
+        problems: Vec<String>,
+    ) -> Result<Settings, SettingsError> {
+        let mut values = BTreeMap::new();
+        if let Some((source, file_values)) = file {
+            merge(&mut values, file_values, &source);
+        }
+        merge(&mut values, env, "environment");
+        merge(&mut values, cli.overrides.iter().cloned(), "command line");
+
+        let mut loader = Loader { values, problems };
+        let settings = Settings::from_loader(&mut loader);
+        loader.finish().map(|_| settings)
+    }
+
+    fn from_loader(l: &mut Loader) -> Settings {
+        let server = ServerSettings {
+            host: l.optional("server.host", "127.0.0.1".to_string()),
+            port: l.optional("server.port", 8000),
+            trust_forwarded_for: l.optional("server.trust_forwarded_for", false),
+        };
+        l.check(server.port != 0, "server.port", "must not be 0");
+
+        let database = DatabaseSettings {
+            url: l.required("database.url"),
+            name: l.optional("database.name", "blog".to_string()),
+            max_connections: l.optional("database.max_connections", 5),
+        };
+        l.check(
+            database
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_'),
+            "database.name",
+            "may only contain ASCII letters, digits and underscores",
+        );
+        l.check(
+            database.max_connections > 0,
+            "database.max_connections",
+            "must be at least 1",
+        );
+
+        let cors = CorsSettings {
+            allowed_origins: l.list("cors.allowed_origins", &["http://127.0.0.1:3000"]),
+        };
+        l.check(
+            cors.allowed_origins
+                .iter()
+                .all(|o| o.starts_with("http://") || o.starts_with("https://")),
+            "cors.allowed_origins",
+            "every origin must start with http:// or https://",
+        );
+
+        let algorithm = l.optional("jwt.algorithm", Algorithm::HS256);
+        l.check(
+            SUPPORTED_ALGORITHMS.contains(&algorithm),
+            "jwt.algorithm",
+            "must be one of HS256, RS256, ES256 or EdDSA",
+        );
+        // With a key ring the keys live in its directory instead of the settings
+        let key_ring_dir: Option<PathBuf> = l.maybe("jwt.key_ring_dir");
+        let symmetric = algorithm == Algorithm::HS256;
+
+        let jwt = JwtSettings {
+            algorithm,
+            key_id: l.optional("jwt.key_id", "default".to_string()),
+            secret: if symmetric && key_ring_dir.is_none() {
+                l.required("jwt.secret")
+            } else {
+                l.optional("jwt.secret", String::new())
+            },
+            private_key_file: if symmetric || key_ring_dir.is_some() {
+                l.maybe("jwt.private_key_file")
+            } else {
+                Some(l.required("jwt.private_key_file"))
+            },
+            key_ring_dir,
+            key_grace_period_days: l.optional("jwt.key_grace_period_days", 7),
+            key_reload_interval_secs: l.optional("jwt.key_reload_interval_secs", 60),
+            access_token_ttl_secs: l.optional("jwt.access_token_ttl_secs", 5),
+            refresh_token_ttl_days: l.optional("jwt.refresh_token_ttl_days", 365),
+            refresh_token_reuse_grace_secs: l.optional("jwt.refresh_token_reuse_grace_secs", 10),
+        };
+        l.check(
+            jwt.access_token_ttl_secs > 0,
+            "jwt.access_token_ttl_secs",
+            "must be positive",
+        );
+        l.check(
+            jwt.refresh_token_ttl_days > 0,
+            "jwt.refresh_token_ttl_days",
+            "must be positive",
+        );
+        l.check(
+            jwt.refresh_token_reuse_grace_secs >= 0,
+            "jwt.refresh_token_reuse_grace_secs",
+            "must not be negative",
+        );
+        l.check(
+            jwt.key_grace_period_days >= 0,
+            "jwt.key_grace_period_days",
+            "must not be negative",
+        );
+        l.check(
+            jwt.key_reload_interval_secs > 0,
+            "jwt.key_reload_interval_secs",
+            "must be positive",
+        );
+
+        let auth = AuthSettings {
+            token_transport: l.optional("auth.token_transport", TokenTransport::Either),
+            unverified_access: l.optional("auth.unverified_access", UnverifiedAccess::Read),
+            email_verification_ttl_hours: l.optional("auth.email_verification_ttl_hours", 24),
+            password_reset_ttl_minutes: l.optional("auth.password_reset_ttl_minutes", 30),
+            totp_issuer: l.optional("auth.totp_issuer", "Blog".to_string()),
+            mfa_token_ttl_secs: l.optional("auth.mfa_token_ttl_secs", 300),
+            max_failed_logins: l.optional("auth.max_failed_logins", 5),
+            max_failed_logins_per_ip: l.optional("auth.max_failed_logins_per_ip", 20),
+            login_backoff_base_secs: l.optional("auth.login_backoff_base_secs", 1),
+            lockout_secs: l.optional("auth.lockout_secs", 900),
+        };
+        l.check(
+            auth.email_verification_ttl_hours > 0,
+            "auth.email_verification_ttl_hours",
+            "must be positive",
+        );
+        l.check(
+            auth.password_reset_ttl_minutes > 0,
+            "auth.password_reset_ttl_minutes",
+            "must be positive",
+        );
+        l.check(
+            !auth.totp_issuer.trim().is_empty(),
+            "auth.totp_issuer",
+            "must not be empty",
+        );
+        l.check(
+            auth.mfa_token_ttl_secs > 0,
+            "auth.mfa_token_ttl_secs",
+            "must be positive",
+        );
+        l.check(
+            auth.max_failed_logins > 0,
+            "auth.max_failed_logins",
+            "must be at least 1",
+        );
+        l.check(
+            auth.max_failed_logins_per_ip > 0,
+            "auth.max_failed_logins_per_ip",
+            "must be at least 1",
+        );
+        l.check(
+            auth.login_backoff_base_secs >= 0,
+            "auth.login_backoff_base_secs",
+            "must not be negative",
+        );
+        l.check(
+            auth.lockout_secs > 0,
+            "auth.lockout_secs",
+            "must be positive",
+        );
+
+        let posts = PostsSettings {
+            publish_interval_secs: l.optional("posts.publish_interval_secs", 60),
+            trash_retention_days: l.optional("posts.trash_retention_days", 30),
+            purge_interval_secs: l.optional("posts.purge_interval_secs", 3600),
+        };
+        l.check(
+            posts.publish_interval_secs > 0,
+            "posts.publish_interval_secs",
+            "must be positive",
+        );
+        l.check(
+            posts.trash_retention_days >= 0,
+            "posts.trash_retention_days",
+            "must not be negative",
+        );
+        l.check(
+            posts.purge_interval_secs > 0,
+            "posts.purge_interval_secs",
+            "must be positive",
+        );
+
+        // No default, so links carrying tokens never end up somewhere by accident
+        let transport = l.maybe("email.transport");
+        let email = EmailSettings {
+            transport,
+            from: l.optional(
+                "email.from",
+                Mailbox::new(None, "no-reply@localhost".parse().expect("valid address")),
+            ),
+            link_base_url: l.optional("email.link_base_url", "http://127.0.0.1:3000".to_string()),
+            smtp_host: if transport == Some(EmailTransport::Smtp) {
+                l.required("email.smtp_host")
+            } else {
+                l.optional("email.smtp_host", String::new())
+            },
+            smtp_port: l.optional("email.smtp_port", 587),
+            smtp_security: l.optional("email.smtp_security", SmtpSecurity::StartTls),
+            smtp_username: l.maybe("email.smtp_username"),
+            smtp_password: l.maybe("email.smtp_password"),
+            file_dir: l.optional("email.file_dir", PathBuf::from("mail")),
+        };
+        l.check(email.smtp_port != 0, "email.smtp_port", "must not be 0");
+        l.check(
+            email.link_base_url.starts_with("http://")
+                || email.link_base_url.starts_with("https://"),
+            "email.link_base_url",
+            "must start with http:// or https://",
+        );
+
+        Settings {
+            server,
+            database,
+            cors,
+            jwt,
+            auth,
+            posts,
+            email,
+        }
+    }
+}
+
+// Startup configuration report listing every problem found
+#[derive(Debug)]
+pub struct SettingsError {
+    pub problems: Vec<String>,
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "invalid configuration ({} problem(s)):",
+            self.problems.len()
+        )?;
+        for problem in &self.problems {
+            writeln!(f, "  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for SettingsError {}
+
+// Parsed command line: `--config <file>`, `--<key>=<value>` overrides and positional arguments
+#[derive(Debug, Default)]
+pub struct CommandLine {
+    pub config_file: Option<PathBuf>,
+    pub overrides: Vec<(String, String)>,
+    pub args: Vec<String>,
+}
+
+impl CommandLine {
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<CommandLine, SettingsError> {
+        let mut cli = CommandLine::default();
+        let mut problems = Vec::new();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let Some(flag) = arg.strip_prefix("--") else {
+                cli.args.push(arg);
+                continue;
+            };
+
+            let (key, value) = match flag.split_once('=') {
+                Some((key, value)) => (key.to_string(), Some(value.to_string())),
+                None => (flag.to_string(), args.next()),
+            };
+
+            let Some(value) = value else {
+                problems.push(format!("missing value for command line flag `--{}`", key));
+                continue;
+            };
+
+            if key == "config" {
+                cli.config_file = Some(PathBuf::from(value));
+            } else {
+                cli.overrides.push((key, value));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(cli)
+        } else {
+            Err(SettingsError { problems })
+        }
+    }
+}
+
+// Collects values by key and records every problem instead of stopping at the first one
+struct Loader {
+    values: BTreeMap<String, (String, String)>,
+    problems: Vec<String>,
+}
+
+impl Loader {
+    fn required<T>(&mut self, key: &str) -> T
+    where
+        T: FromStr + Default,
+        T::Err: fmt::Display,
+    {
+        match self.values.remove(key) {
+            Some((value, source)) if !value.trim().is_empty() => {
+                self.parse(key, &value, &source).unwrap_or_default()
+            }
+            _ => {
+                self.problems.push(format!(
+                    "missing required setting `{}` (set it in {}, {} or --{})",
+                    key,
+                    DEFAULT_CONFIG_FILE,
+                    env_name(key),
+                    key
+                ));
+                T::default()
+            }
+        }
+    }
+
+    fn optional<T>(&mut self, key: &str, default: T) -> T
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        match self.values.remove(key) {
+            Some((value, source)) => self.parse(key, &value, &source).unwrap_or(default),
+            None => default,
+        }
+    }
+
+    fn maybe<T>(&mut self, key: &str) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        let (value, source) = self.values.remove(key)?;
+        self.parse(key, &value, &source)
+    }
+
+    fn list(&mut self, key: &str, default: &[&str]) -> Vec<String> {
+        match self.values.remove(key) {
+            Some((value, _)) => value
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(String::from)
+                .collect(),
+            None => default.iter().map(|item| item.to_string()).collect(),
+        }
+    }
+
+    fn check(&mut self, valid: bool, key: &str, message: &str) {
+        if !valid {
+            self.problems
+                .push(format!("invalid value for `{}`: {}", key, message));
+        }
+    }
+
+    fn parse<T>(&mut self, key: &str, value: &str, source: &str) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        match value.trim().parse() {
+            Ok(parsed) => Some(parsed),
+            Err(e) => {
+                self.problems.push(format!(
+                    "invalid value `{}` for `{}` from {}: {}",
+                    value, key, source, e
+                ));
+                None
+            }
+        }
+    }
+
+    fn finish(mut self) -> Result<(), SettingsError> {
+        for (key, (_, source)) in &self.values {
+            self.problems
+                .push(format!("unknown setting `{}` from {}", key, source));
+        }
+
+        if self.problems.is_empty() {
+            Ok(())
+        } else {
+            Err(SettingsError {
+                problems: self.problems,
+            })
+        }
+    }
+}
+
+fn merge<I>(values: &mut BTreeMap<String, (String, String)>, layer: I, source: &str)
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    for (key, value) in layer {
+        values.insert(key, (value, source.to_string()));
+    }
+}
+
+// Config file given on the command line or environment, otherwise config.toml when present
+fn config_file(cli: &CommandLine) -> Result<Option<PathBuf>, String> {
+    let explicit = cli
+        .config_file
+        .clone()
+        .or_else(|| env::var_os(ENV_CONFIG_FILE).map(PathBuf::from));
+
+    match explicit {
+        Some(path) if path.is_file() => Ok(Some(path)),
+        Some(path) => Err(format!("config file {} does not exist", path.display())),
+        None => {
+            let default = PathBuf::from(DEFAULT_CONFIG_FILE);
+            Ok(default.is_file().then_some(default))
+        }
+    }
+}
+
+fn read_config_file(path: &Path) -> Result<Vec<(String, String)>, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("failed to read config file {}: {}", path.display(), e))?;
+    let table: toml::Table = contents
+        .parse()
+        .map_err(|e| format!("failed to parse config file {}: {}", path.display(), e))?;
+
+    let mut values = Vec::new();
+    flatten_table("", &table, &mut values);
+    Ok(values)
+}
+
+// Flatten nested tables into dotted keys, arrays into comma separated lists
+fn flatten_table(prefix: &str, table: &toml::Table, out: &mut Vec<(String, String)>) {
+    for (name, value) in table {
+        let key = if prefix.is_empty() {
+            name.clone()
+        } else {
+            format!("{}.{}", prefix, name)
+        };
+
+        match value {
+            toml::Value::Table(inner) => flatten_table(&key, inner, out),
+            toml::Value::Array(items) => {
+                let items: Vec<String> = items.iter().map(toml_scalar).collect();
+                out.push((key, items.join(",")));
+            }
+            scalar => out.push((key, toml_scalar(scalar))),
+        }
+    }
+}
+
+fn toml_scalar(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+// BLOG_DATABASE__MAX_CONNECTIONS -> database.max_connections; DATABASE_URL is honoured as well
+fn env_values<I: IntoIterator<Item = (String, String)>>(vars: I) -> Vec<(String, String)> {
+    let mut fallback = Vec::new();
+    let mut prefixed = Vec::new();
+
+    for (name, value) in vars {
+        if name == "DATABASE_URL" {
+            fallback.push(("database.url".to_string(), value));
+        } else if name == ENV_CONFIG_FILE {
+            continue;
+        } else if let Some(key) = name.strip_prefix(ENV_PREFIX) {
+            prefixed.push((key.to_lowercase().replace("__", "."), value));
+        }
+    }
+
+    fallback.extend(prefixed);
+    fallback
+}
+
+fn env_name(key: &str) -> String {
+    format!("{}{}", ENV_PREFIX, key.to_uppercase().replace('.', "__"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(contents: &str) -> Option<(String, Vec<(String, String)>)> {
+        let table: toml::Table = contents.parse().unwrap();
+        let mut values = Vec::new();
+        flatten_table("", &table, &mut values);
+        Some(("file config.toml".to_string(), values))
+    }
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        env_values(
+            vars.iter()
+                .map(|(name, value)| (name.to_string(), value.to_string())),
+        )
+    }
+
+    fn cli(args: &[&str]) -> CommandLine {
+        CommandLine::parse(args.iter().map(|arg| arg.to_string())).unwrap()
+    }
+
+    const MINIMAL: &str = r#"
+        [database]
+        url = "postgres://localhost"
+        [jwt]
+        secret = "s3cret"
+    "#;
+
+    #[test]
+    fn flattens_nested_tables_and_arrays() {
+        let (_, mut values) = file(
+            r#"
+                [server]
+                port = 8080
+                [cors]
+                allowed_origins = ["http://a.io", "http://b.io"]
+                [jwt]
+                secret = "s"
+            "#,
+        )
+        .unwrap();
+        values.sort();
+
+        assert_eq!(
+            values,
+            vec![
+                (
+                    "cors.allowed_origins".to_string(),
+                    "http://a.io,http://b.io".to_string()
+                ),
+                ("jwt.secret".to_string(), "s".to_string()),
+                ("server.port".to_string(), "8080".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn maps_environment_variables_to_keys() {
+        assert_eq!(
+            env(&[
+                ("BLOG_DATABASE__MAX_CONNECTIONS", "7"),
+                ("DATABASE_URL", "postgres://env"),
+                ("BLOG_CONFIG", "other.toml"),
+                ("HOME", "/root"),
+            ]),
+            vec![
+                ("database.url".to_string(), "postgres://env".to_string()),
+                ("database.max_connections".to_string(), "7".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn prefixed_database_url_beats_the_plain_one() {
+        let settings = Settings::from_layers(
+            file(MINIMAL),
+            env(&[
+                ("BLOG_DATABASE__URL", "postgres://prefixed"),
+                ("DATABASE_URL", "postgres://plain"),
+            ]),
+            &cli(&[]),
+            Vec::new(),
+        )
+        .unwrap();
+        assert_eq!(settings.database.url, "postgres://prefixed");
+    }
+
+    #[test]
+    fn later_layers_take_precedence() {
+        let layers = |env_port: Option<&str>, cli_port: Option<&str>| {
+            let env_vars: Vec<(&str, &str)> = env_port
+                .map(|port| ("BLOG_SERVER__PORT", port))
+                .into_iter()
+                .collect();
+            let args: Vec<String> = cli_port
+                .map(|port| format!("--server.port={}", port))
+                .into_iter()
+                .collect();
+            let args: Vec<&str> = args.iter().map(String::as_str).collect();
+            let file = file(&format!("{}\n[server]\nport = 1000\n", MINIMAL));
+            Settings::from_layers(file, env(&env_vars), &cli(&args), Vec::new())
+                .unwrap()
+                .server
+                .port
+        };
+
+        assert_eq!(
+            Settings::from_layers(file(MINIMAL), Vec::new(), &cli(&[]), Vec::new())
+                .unwrap()
+                .server
+                .port,
+            8000
+        );
+        assert_eq!(layers(None, None), 1000);
+        assert_eq!(layers(Some("2000"), None), 2000);
+        assert_eq!(layers(Some("2000"), Some("3000")), 3000);
+        assert_eq!(layers(None, Some("3000")), 3000);
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        let err = Settings::from_layers(
+            file(&format!("{}\n[server]\nprot = 80\n", MINIMAL)),
+            Vec::new(),
+            &cli(&[]),
+            Vec::new(),
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            err.problems,
+            vec!["unknown setting `server.prot` from file config.toml".to_string()]
+        );
+    }
+
+    #[test]
+    fn collects_every_problem() {
+        let err = Settings::from_layers(
+            None,
+            env(&[("BLOG_SERVER__PORT", "http")]),
+            &cli(&[
+                "--server.host=0.0.0.0",
+                "--jwt.access_token_ttl_secs",
+                "0",
+                "--nope=1",
+            ]),
+            vec!["config file missing.toml does not exist".to_string()],
+        )
+        .err()
+        .unwrap();
+
+        let problems = err.problems.join("\n");
+        assert_eq!(err.problems.len(), 6, "{}", problems);
+        assert!(problems.contains("config file missing.toml does not exist"));
+        assert!(problems.contains("invalid value `http` for `server.port` from environment"));
+        assert!(problems.contains("missing required setting `database.url`"));
+        assert!(problems.contains("missing required setting `jwt.secret`"));
+        assert!(problems.contains("invalid value for `jwt.access_token_ttl_secs`"));
+        assert!(problems.contains("unknown setting `nope` from command line"));
+        assert!(err
+            .to_string()
+            .starts_with("invalid configuration (6 problem(s)):"));
+    }
+
+    #[test]
+    fn parses_command_lines() {
+        let cli = cli(&[
+            "--config",
+            "prod.toml",
+            "--server.port=9000",
+            "keys",
+            "list",
+        ]);
+        assert_eq!(cli.config_file, Some(PathBuf::from("prod.toml")));
+        assert_eq!(
+            cli.overrides,
+            vec![("server.port".to_string(), "9000".to_string())]
+        );
+        assert_eq!(cli.args, vec!["keys".to_string(), "list".to_string()]);
+
+        let err = CommandLine::parse(vec!["--server.port".to_string()]).unwrap_err();
+        assert_eq!(
+            err.problems,
+            vec!["missing value for command line flag `--server.port`".to_string()]
+        );
+    }
+}
@@ -0,0 +1,79 @@
+use deunicode::deunicode_with_tofu;
+
+// Longest slug derived from a title, collision suffixes come on top
+const MAX_SLUG_LENGTH: usize = 80;
+
+// Slug given to titles without a single letter or digit
+const FALLBACK_SLUG: &str = "post";
+
+// URL-safe slug of a title: transliterated to ASCII and lowercased, with every run of other
+// characters turned into a single dash
+pub fn slugify(title: &str) -> String {
+    let mut slug = String::new();
+    for c in deunicode_with_tofu(title, " ").chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+
+    slug.truncate(MAX_SLUG_LENGTH);
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() {
+        FALLBACK_SLUG.to_string()
+    } else {
+        slug.to_string()
+    }
+}
+
+// The `n`th candidate slug for a base: the base itself, then base-2, base-3...
+pub fn candidate_slug(base: &str, n: usize) -> String {
+    match n {
+        1 => base.to_string(),
+        n => format!("{}-{}", base, n),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lowercases_and_dashes_words() {
+        assert_eq!(slugify("Hello, World!"), "hello-world");
+        assert_eq!(slugify("  Rust -- async   & await  "), "rust-async-await");
+    }
+
+    #[test]
+    fn transliterates_unicode() {
+        assert_eq!(slugify("Ünïcödé Straße"), "unicode-strasse");
+        assert_eq!(slugify("Привет, мир"), "privet-mir");
+        assert_eq!(slugify("北京"), "bei-jing");
+    }
+
+    #[test]
+    fn falls_back_without_letters_or_digits() {
+        assert_eq!(slugify(""), FALLBACK_SLUG);
+        assert_eq!(slugify("!!! ??? ..."), FALLBACK_SLUG);
+    }
+
+    #[test]
+    fn truncates_long_titles_without_a_trailing_dash() {
+        assert_eq!(slugify(&"a".repeat(200)), "a".repeat(MAX_SLUG_LENGTH));
+
+        let title = format!("{} next", "b".repeat(MAX_SLUG_LENGTH));
+        assert_eq!(slugify(&title), "b".repeat(MAX_SLUG_LENGTH));
+
+        // The cut lands on the dash after a word, which is dropped
+        let title = format!("{} next", "c".repeat(MAX_SLUG_LENGTH - 1));
+        assert_eq!(slugify(&title), "c".repeat(MAX_SLUG_LENGTH - 1));
+    }
+
+    #[test]
+    fn numbers_candidates_after_the_first() {
+        assert_eq!(candidate_slug("hello", 1), "hello");
+        assert_eq!(candidate_slug("hello", 2), "hello-2");
+        assert_eq!(candidate_slug("hello", 10), "hello-10");
+    }
+}
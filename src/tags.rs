@@ -0,0 +1,127 @@
+use std::future::{ready, Ready};
+
+use actix_web::{dev::Payload, web, FromRequest, HttpRequest};
+use serde::Deserialize;
+
+use crate::error::AppError;
+
+// Most tags a post may carry, and a listing may be filtered by
+pub const MAX_TAGS: usize = 10;
+
+// Longest tag, in characters
+pub const MAX_TAG_LENGTH: usize = 50;
+
+// Canonical form of a tag: trimmed and case folded, with every run of inner whitespace turned
+// into a single dash, so `  Web   Dev ` and `web-dev` are the same tag
+pub fn normalize_tag(tag: &str) -> String {
+    tag.split_whitespace()
+        .collect::<Vec<_>>()
+        .join("-")
+        .to_lowercase()
+}
+
+// Canonical tags, sorted and without duplicates
+pub fn normalize_tags(tags: &[String]) -> Vec<String> {
+    let mut tags: Vec<String> = tags.iter().map(|tag| normalize_tag(tag)).collect();
+    tags.sort();
+    tags.dedup();
+    tags
+}
+
+// Whether a listing filtered by several tags wants posts with any or with all of them
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TagMatch {
+    #[default]
+    Any,
+    All,
+}
+
+// Tags from the repeated `tag` parameters of a listing's query string, e.g.
+// `?tag=rust&tag=web`. The usual query extractor can't collect repeated keys.
+#[derive(Debug, Default)]
+pub struct TagQuery(pub Vec<String>);
+
+impl TagQuery {
+    // Canonical tags to filter by, rejecting ones no post could carry
+    pub fn normalized(&self) -> Result<Vec<String>, AppError> {
+        let tags = normalize_tags(&self.0);
+        if tags.iter().any(|tag| tag.is_empty()) {
+            return Err(AppError::validation("tag", "must not be empty"));
+        }
+        if tags.iter().any(|tag| tag.chars().count() > MAX_TAG_LENGTH) {
+            return Err(AppError::validation(
+                "tag",
+                &format!("must be at most {} characters", MAX_TAG_LENGTH),
+            ));
+        }
+        if tags.len() > MAX_TAGS {
+            return Err(AppError::validation(
+                "tag",
+                &format!("must be given at most {} times", MAX_TAGS),
+            ));
+        }
+        Ok(tags)
+    }
+}
+
+impl FromRequest for TagQuery {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let pairs = web::Query::<Vec<(String, String)>>::from_query(req.query_string());
+        ready(pairs.map_err(AppError::from).map(|pairs| {
+            TagQuery(
+                pairs
+                    .into_inner()
+                    .into_iter()
+                    .filter(|(key, _)| key == "tag")
+                    .map(|(_, value)| value)
+                    .collect(),
+            )
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(tags: &[&str]) -> Vec<String> {
+        tags.iter().map(|tag| tag.to_string()).collect()
+    }
+
+    #[test]
+    fn normalizes_case_and_whitespace() {
+        assert_eq!(normalize_tag("  Web   Dev "), "web-dev");
+        assert_eq!(normalize_tag("Rust\tLang\nTips"), "rust-lang-tips");
+        assert_eq!(normalize_tag("ÜBER"), "über");
+        assert_eq!(normalize_tag("   "), "");
+    }
+
+    #[test]
+    fn sorts_and_dedups_normalized_tags() {
+        assert_eq!(
+            normalize_tags(&tags(&["Web Dev", "rust", "web-dev", " RUST "])),
+            tags(&["rust", "web-dev"])
+        );
+    }
+
+    #[test]
+    fn filter_rejects_tags_no_post_could_carry() {
+        assert!(TagQuery(tags(&["rust", " "])).normalized().is_err());
+        assert!(TagQuery(tags(&[&"a".repeat(MAX_TAG_LENGTH + 1)]))
+            .normalized()
+            .is_err());
+
+        let many: Vec<String> = (0..=MAX_TAGS).map(|i| format!("tag{}", i)).collect();
+        assert!(TagQuery(many).normalized().is_err());
+    }
+
+    #[test]
+    fn filter_counts_tags_after_dedup() {
+        let repeated = vec!["Rust".to_string(); MAX_TAGS + 5];
+        assert_eq!(TagQuery(repeated).normalized().unwrap(), tags(&["rust"]));
+    }
+}
@@ -0,0 +1,278 @@
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+
+use crate::{
+    error::AppError,
+    model::LoginThrottle,
+    queries::{
+        clear_login_throttle, get_login_throttle, get_login_throttle_for_update,
+        update_login_throttle,
+    },
+    settings::AuthSettings,
+    AppState,
+};
+
+// Failed logins are counted per account and per client address. Accounts wait longer after
+// every failure, addresses are only locked once they reach their limit so one mistyped
+// password doesn't hold up everyone behind the same NAT.
+pub const ACCOUNT_SCOPE: &str = "account";
+pub const IP_SCOPE: &str = "ip";
+
+// Longest wait between two attempts short of a lockout
+const MAX_BACKOFF_SECS: i64 = 60;
+
+// Wait the number of failures asks for before the next attempt, doubling with every failure
+fn backoff(failures: i32, settings: &AuthSettings) -> Duration {
+    if failures <= 0 || settings.login_backoff_base_secs == 0 {
+        return Duration::zero();
+    }
+    let doublings = (failures - 1).min(16) as u32;
+    let secs = settings
+        .login_backoff_base_secs
+        .saturating_mul(1i64 << doublings)
+        .min(MAX_BACKOFF_SECS);
+    Duration::seconds(secs)
+}
+
+impl LoginThrottle {
+    // When the next attempt is allowed, and whether it is held back by a lockout rather
+    // than a back-off delay
+    fn next_attempt_at(
+        &self,
+        scope: &str,
+        settings: &AuthSettings,
+    ) -> Option<(DateTime<Utc>, bool)> {
+        if let Some(locked_until) = self.locked_until {
+            return Some((locked_until, true));
+        }
+        if scope != ACCOUNT_SCOPE {
+            return None;
+        }
+        self.last_failed_at
+            .map(|last| (last + backoff(self.failures, settings), false))
+    }
+
+    // Failures older than the lockout period and expired locks no longer count
+    fn is_stale(&self, now: DateTime<Utc>, settings: &AuthSettings) -> bool {
+        match (self.locked_until, self.last_failed_at) {
+            (Some(locked_until), _) => locked_until <= now,
+            (None, Some(last)) => last + Duration::seconds(settings.lockout_secs) <= now,
+            (None, None) => true,
+        }
+    }
+
+    // Count a failure at `now`, starting over if the earlier ones went stale. Returns whether
+    // this failure locked the account or client address.
+    fn record_failure(
+        &mut self,
+        now: DateTime<Utc>,
+        max_failures: i32,
+        settings: &AuthSettings,
+    ) -> bool {
+        if self.is_stale(now, settings) {
+            self.failures = 0;
+            self.locked_until = None;
+        }
+
+        self.failures += 1;
+        self.last_failed_at = Some(now);
+        if self.failures >= max_failures {
+            self.locked_until = Some(now + Duration::seconds(settings.lockout_secs));
+            return true;
+        }
+        false
+    }
+}
+
+// Refuse a login attempt while the account or the client address has to wait
+pub async fn check_login_allowed(state: &AppState, scope: &str, key: &str) -> Result<(), AppError> {
+    let settings = &state.settings.auth;
+    let Some(throttle) = get_login_throttle(&state.pool, scope, key).await? else {
+        return Ok(());
+    };
+
+    let now = Utc::now();
+    match throttle.next_attempt_at(scope, settings) {
+        Some((at, locked)) if at > now => {
+            // Rounded up so clients that wait exactly that long get through
+            let retry_after = (at - now).num_milliseconds().saturating_add(999) / 1000;
+            Err(match (locked, scope) {
+                (true, ACCOUNT_SCOPE) => AppError::TooManyRequests(
+                    "account_locked",
+                    "Too many failed logins, the account is locked for now. Please try again later!"
+                        .to_string(),
+                    retry_after,
+                ),
+                _ => AppError::TooManyRequests(
+                    "too_many_attempts",
+                    "Too many failed logins. Please try again later!".to_string(),
+                    retry_after,
+                ),
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+// Count a failed login, locking the account or client address once it reaches its limit
+pub async fn record_login_failure(state: &AppState, scope: &str, key: &str) -> sqlx::Result<()> {
+    let settings = &state.settings.auth;
+    let max_failures = if scope == ACCOUNT_SCOPE {
+        settings.max_failed_logins
+    } else {
+        settings.max_failed_logins_per_ip
+    };
+
+    let now = Utc::now();
+    let mut tx = state.pool.begin().await?;
+    let mut throttle = get_login_throttle_for_update(&mut tx, scope, key).await?;
+    if throttle.record_failure(now, max_failures, settings) {
+        log::warn!(
+            "login locked for {} {} after {} failures",
+            scope,
+            key,
+            throttle.failures
+        );
+    }
+
+    update_login_throttle(&mut tx, scope, key, &throttle).await?;
+    tx.commit().await
+}
+
+// Forget the failed logins of an account, after a successful login or when an admin unlocks it
+pub async fn reset_account_throttle(state: &AppState, user_id: &Uuid) -> sqlx::Result<()> {
+    clear_login_throttle(&state.pool, ACCOUNT_SCOPE, &user_id.to_string()).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::settings::{TokenTransport, UnverifiedAccess};
+
+    fn settings(base_secs: i64) -> AuthSettings {
+        AuthSettings {
+            token_transport: TokenTransport::Either,
+            unverified_access: UnverifiedAccess::Full,
+            email_verification_ttl_hours: 24,
+            password_reset_ttl_minutes: 30,
+            totp_issuer: "blog".to_string(),
+            mfa_token_ttl_secs: 300,
+            max_failed_logins: 5,
+            max_failed_logins_per_ip: 20,
+            login_backoff_base_secs: base_secs,
+            lockout_secs: 900,
+        }
+    }
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2025, 2, 15, 9, 0, 0).unwrap() + Duration::seconds(secs)
+    }
+
+    fn fresh() -> LoginThrottle {
+        LoginThrottle {
+            failures: 0,
+            last_failed_at: None,
+            locked_until: None,
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_with_every_failure_up_to_the_cap() {
+        let settings = settings(1);
+        let waits: Vec<i64> = (0..=8)
+            .map(|failures| backoff(failures, &settings).num_seconds())
+            .collect();
+        assert_eq!(waits, vec![0, 1, 2, 4, 8, 16, 32, 60, 60]);
+
+        assert_eq!(backoff(-1, &settings), Duration::zero());
+        assert_eq!(
+            backoff(i32::MAX, &settings),
+            Duration::seconds(MAX_BACKOFF_SECS)
+        );
+    }
+
+    #[test]
+    fn backoff_is_disabled_by_a_zero_base() {
+        assert_eq!(backoff(10, &settings(0)), Duration::zero());
+    }
+
+    #[test]
+    fn backoff_does_not_overflow_with_a_huge_base() {
+        assert_eq!(
+            backoff(3, &settings(i64::MAX)),
+            Duration::seconds(MAX_BACKOFF_SECS)
+        );
+    }
+
+    #[test]
+    fn only_accounts_back_off_between_failures() {
+        let settings = settings(2);
+        let throttle = LoginThrottle {
+            failures: 3,
+            last_failed_at: Some(at(0)),
+            locked_until: None,
+        };
+        assert_eq!(
+            throttle.next_attempt_at(ACCOUNT_SCOPE, &settings),
+            Some((at(8), false))
+        );
+        assert_eq!(throttle.next_attempt_at(IP_SCOPE, &settings), None);
+        assert_eq!(fresh().next_attempt_at(ACCOUNT_SCOPE, &settings), None);
+    }
+
+    #[test]
+    fn reaching_the_limit_locks_out() {
+        let settings = settings(1);
+        let mut throttle = fresh();
+        for i in 0..4 {
+            assert!(!throttle.record_failure(at(i), 5, &settings));
+        }
+        assert_eq!(throttle.locked_until, None);
+
+        assert!(throttle.record_failure(at(4), 5, &settings));
+        assert_eq!(throttle.failures, 5);
+        assert_eq!(throttle.locked_until, Some(at(904)));
+        for scope in [ACCOUNT_SCOPE, IP_SCOPE] {
+            assert_eq!(
+                throttle.next_attempt_at(scope, &settings),
+                Some((at(904), true))
+            );
+        }
+    }
+
+    #[test]
+    fn stale_failures_start_over() {
+        let settings = settings(1);
+        let mut throttle = LoginThrottle {
+            failures: 4,
+            last_failed_at: Some(at(0)),
+            locked_until: None,
+        };
+        assert!(!throttle.is_stale(at(899), &settings));
+        assert!(throttle.is_stale(at(900), &settings));
+
+        assert!(!throttle.record_failure(at(900), 5, &settings));
+        assert_eq!(throttle.failures, 1);
+        assert_eq!(throttle.last_failed_at, Some(at(900)));
+    }
+
+    #[test]
+    fn expired_locks_start_over() {
+        let settings = settings(1);
+        let mut throttle = LoginThrottle {
+            failures: 5,
+            last_failed_at: Some(at(0)),
+            locked_until: Some(at(900)),
+        };
+        assert!(!throttle.is_stale(at(899), &settings));
+        assert!(throttle.is_stale(at(900), &settings));
+        assert!(fresh().is_stale(at(0), &settings));
+
+        assert!(!throttle.record_failure(at(900), 5, &settings));
+        assert_eq!(throttle.failures, 1);
+        assert_eq!(throttle.locked_until, None);
+    }
+}
@@ -0,0 +1,297 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+
+// RFC 6238 parameters every common authenticator app understands
+pub const TOTP_DIGITS: u32 = 6;
+pub const TOTP_PERIOD_SECS: i64 = 30;
+
+// Codes from one step before or after the current one are accepted, for clock drift
+const TOTP_SKEW_STEPS: i64 = 1;
+
+// Length of a secret in bytes, as RFC 4226 recommends
+const SECRET_LENGTH: usize = 20;
+
+// Recovery codes handed out when two-factor authentication is enabled
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+// Random secret for a new authenticator
+pub fn generate_secret() -> Vec<u8> {
+    let mut secret = vec![0u8; SECRET_LENGTH];
+    OsRng.fill_bytes(&mut secret);
+    secret
+}
+
+// Time step a unix timestamp falls into
+pub fn time_step(unix_secs: i64) -> i64 {
+    unix_secs.div_euclid(TOTP_PERIOD_SECS)
+}
+
+// RFC 4226 one-time password for a counter
+pub fn hotp(secret: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    // Dynamic truncation
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    binary % 10u32.pow(TOTP_DIGITS)
+}
+
+// Time step a code is valid for around `now`, if any. Steps up to `last_step` were used
+// before and are rejected so a code can't be replayed.
+pub fn verify_code(secret: &[u8], code: &str, now: i64, last_step: Option<i64>) -> Option<i64> {
+    if !is_totp_code(code) {
+        return None;
+    }
+    let code: u32 = code.trim().parse().ok()?;
+
+    let current = time_step(now);
+    (current - TOTP_SKEW_STEPS..=current + TOTP_SKEW_STEPS)
+        .filter(|step| *step >= 0 && last_step.is_none_or(|last| *step > last))
+        .find(|step| hotp(secret, *step as u64) == code)
+}
+
+// Whether a code is shaped like a TOTP code rather than a recovery code
+pub fn is_totp_code(code: &str) -> bool {
+    let code = code.trim();
+    code.len() == TOTP_DIGITS as usize && code.bytes().all(|b| b.is_ascii_digit())
+}
+
+// Unpadded RFC 4648 base32, the encoding authenticator apps expect secrets in
+pub fn base32_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity((bytes.len() * 8).div_ceil(5));
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for byte in bytes {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+
+    out
+}
+
+// Key URI authenticator apps read from a QR code, see
+// https://github.com/google/google-authenticator/wiki/Key-Uri-Format
+pub fn otpauth_uri(issuer: &str, account: &str, secret: &[u8]) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        percent_encode(issuer),
+        percent_encode(account),
+        base32_encode(secret),
+        percent_encode(issuer),
+        TOTP_DIGITS,
+        TOTP_PERIOD_SECS
+    )
+}
+
+// Random recovery codes such as `k3x9q-7mwp2`
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let mut bytes = [0u8; 7];
+            OsRng.fill_bytes(&mut bytes);
+            let code = base32_encode(&bytes).to_lowercase();
+            format!("{}-{}", &code[..5], &code[5..10])
+        })
+        .collect()
+}
+
+// Canonical form of a recovery code, which is what gets hashed
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+fn percent_encode(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                out.push(byte as char)
+            }
+            _ => out.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Secret of the RFC 4226 and RFC 6238 test vectors
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    fn code_at(step: i64) -> String {
+        format!("{:06}", hotp(RFC_SECRET, step as u64))
+    }
+
+    #[test]
+    fn matches_rfc_4226_hotp_vectors() {
+        let expected = [
+            755224, 287082, 359152, 969429, 338314, 254676, 287922, 162583, 399871, 520489,
+        ];
+        for (counter, code) in expected.into_iter().enumerate() {
+            assert_eq!(
+                hotp(RFC_SECRET, counter as u64),
+                code,
+                "counter {}",
+                counter
+            );
+        }
+    }
+
+    #[test]
+    fn matches_rfc_6238_sha1_vectors() {
+        // Appendix B lists 8 digit codes, their last 6 digits are the 6 digit codes
+        let expected = [
+            (59, "94287082"),
+            (1111111109, "07081804"),
+            (1111111111, "14050471"),
+            (1234567890, "89005924"),
+            (2000000000, "69279037"),
+            (20000000000, "65353130"),
+        ];
+        for (time, code) in expected {
+            let code = &code[2..];
+            assert_eq!(code_at(time_step(time)), code, "time {}", time);
+            assert_eq!(
+                verify_code(RFC_SECRET, code, time, None),
+                Some(time_step(time)),
+                "time {}",
+                time
+            );
+        }
+    }
+
+    #[test]
+    fn accepts_one_step_of_clock_drift() {
+        let now = 1234567890;
+        let step = time_step(now);
+
+        for drift in [-1, 0, 1] {
+            assert_eq!(
+                verify_code(RFC_SECRET, &code_at(step + drift), now, None),
+                Some(step + drift)
+            );
+        }
+        for drift in [-2, 2] {
+            assert_eq!(
+                verify_code(RFC_SECRET, &code_at(step + drift), now, None),
+                None
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_replayed_steps() {
+        let now = 1234567890;
+        let step = time_step(now);
+        let code = code_at(step);
+
+        assert_eq!(verify_code(RFC_SECRET, &code, now, Some(step)), None);
+        assert_eq!(verify_code(RFC_SECRET, &code, now, Some(step + 1)), None);
+        assert_eq!(
+            verify_code(RFC_SECRET, &code, now, Some(step - 1)),
+            Some(step)
+        );
+
+        // A code from before the last used step stays rejected even within the window
+        assert_eq!(
+            verify_code(RFC_SECRET, &code_at(step - 1), now, Some(step - 1)),
+            None
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_codes() {
+        let now = 1234567890;
+        let code = code_at(time_step(now));
+
+        assert_eq!(
+            verify_code(RFC_SECRET, &format!(" {} ", code), now, None),
+            Some(time_step(now))
+        );
+        assert_eq!(verify_code(RFC_SECRET, &code[..5], now, None), None);
+        assert_eq!(
+            verify_code(RFC_SECRET, &format!("{}0", code), now, None),
+            None
+        );
+        assert_eq!(verify_code(RFC_SECRET, "12a456", now, None), None);
+        assert_eq!(verify_code(RFC_SECRET, "+12345", now, None), None);
+    }
+
+    #[test]
+    fn matches_rfc_4648_base32_vectors() {
+        let expected = [
+            ("", ""),
+            ("f", "MY"),
+            ("fo", "MZXQ"),
+            ("foo", "MZXW6"),
+            ("foob", "MZXW6YQ"),
+            ("fooba", "MZXW6YTB"),
+            ("foobar", "MZXW6YTBOI"),
+        ];
+        for (input, encoded) in expected {
+            assert_eq!(base32_encode(input.as_bytes()), encoded, "{:?}", input);
+        }
+        assert_eq!(
+            base32_encode(RFC_SECRET),
+            "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ"
+        );
+    }
+
+    #[test]
+    fn builds_key_uris() {
+        assert_eq!(
+            otpauth_uri("My Blog", "ada@x.io", RFC_SECRET),
+            "otpauth://totp/My%20Blog:ada%40x.io?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ\
+             &issuer=My%20Blog&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+
+    #[test]
+    fn generates_distinct_recovery_codes() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+
+        for code in &codes {
+            let (left, right) = code.split_once('-').expect("dash in the middle");
+            assert_eq!((left.len(), right.len()), (5, 5), "{}", code);
+            assert!(code
+                .chars()
+                .all(|c| c == '-' || c.is_ascii_lowercase() || ('2'..='7').contains(&c)));
+            assert_eq!(normalize_recovery_code(code), format!("{}{}", left, right));
+        }
+
+        let mut unique = codes.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), codes.len());
+    }
+
+    #[test]
+    fn normalizes_recovery_codes() {
+        assert_eq!(normalize_recovery_code(" K3X9Q-7mwp2 "), "k3x9q7mwp2");
+        assert_eq!(normalize_recovery_code("k3x9q 7mwp2"), "k3x9q7mwp2");
+    }
+}
@@ -1,51 +1,88 @@
+use std::{net::IpAddr, sync::LazyLock};
+
+use actix_web::{
+    cookie::{time::OffsetDateTime, Cookie},
+    web::ReqData,
+    HttpRequest,
+};
 use argon2::{
-    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    password_hash::{
+        rand_core::{OsRng, RngCore},
+        PasswordHash, PasswordHasher, PasswordVerifier, SaltString,
+    },
     Argon2,
 };
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use chrono::{Duration, Utc};
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, TokenData, Validation};
+use jsonwebtoken::{
+    decode, decode_header, encode, errors::ErrorKind, Header, TokenData, Validation,
+};
+use sha2::{Digest, Sha256};
 use uuid::Uuid;
 
-use crate::model::Claim;
+use crate::{
+    error::AppError, keys::JwtKeys, model::Claim, session::IssuedRefreshToken,
+    settings::JwtSettings,
+};
 
-// generate access token
-pub fn generate_access_token(user_id: &str, secret: &str) -> jsonwebtoken::errors::Result<String> {
-    let claim = Claim {
+// claim of an access token issued now
+pub fn access_claim(
+    user_id: &str,
+    roles: Vec<String>,
+    email_verified: bool,
+    jwt: &JwtSettings,
+) -> Claim {
+    Claim {
         sub: user_id.to_string(),
         iat: Utc::now().timestamp() as usize,
-        exp: (Utc::now() + Duration::seconds(5)).timestamp() as usize,
-    };
-
-    encode(
-        &Header::default(),
-        &claim,
-        &EncodingKey::from_secret(secret.as_ref()),
-    )
+        exp: (Utc::now() + Duration::seconds(jwt.access_token_ttl_secs)).timestamp() as usize,
+        roles,
+        email_verified,
+    }
 }
 
-//generate refresh token
-pub fn generate_refresh_token(user_id: &str, secret: &str) -> jsonwebtoken::errors::Result<String> {
-    let claim = Claim {
-        sub: user_id.to_string(),
-        iat: Utc::now().timestamp() as usize,
-        exp: (Utc::now() + Duration::days(365)).timestamp() as usize,
-    };
-
-    encode(
-        &Header::default(),
-        &claim,
-        &EncodingKey::from_secret(secret.as_ref()),
-    )
+// generate an opaque token for refresh and emailed links, only its hash is persisted
+pub fn generate_token() -> String {
+    let mut token = [0u8; 32];
+    OsRng.fill_bytes(&mut token);
+    URL_SAFE_NO_PAD.encode(token)
 }
 
-// decode tokens
-pub fn decode_token(token: &str, secret: &str) -> jsonwebtoken::errors::Result<TokenData<Claim>> {
-    let validation = Validation::default();
-    decode::<Claim>(
-        token,
-        &DecodingKey::from_secret(secret.as_ref()),
-        &validation,
-    )
+// cookie carrying a refresh token until it expires
+pub fn refresh_token_cookie(refresh_token: &IssuedRefreshToken) -> Cookie<'static> {
+    let expires = OffsetDateTime::from_unix_timestamp(refresh_token.expires_at.timestamp())
+        .unwrap_or_else(|_| OffsetDateTime::now_utc());
+
+    Cookie::build("refresh_token", refresh_token.token.clone())
+        .http_only(true)
+        .path("/")
+        .expires(expires)
+        .finish()
+}
+
+// hash a token for storage and lookup
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+// sign a claim with the current key, naming it in the kid header
+pub fn sign_claim(claim: &Claim, keys: &JwtKeys) -> jsonwebtoken::errors::Result<String> {
+    let key = keys.signing_key();
+    let mut header = Header::new(key.algorithm);
+    header.kid = Some(key.kid.clone());
+
+    encode(&header, claim, key.encoding_key())
+}
+
+// decode tokens with the key named by their kid header
+pub fn decode_token(token: &str, keys: &JwtKeys) -> jsonwebtoken::errors::Result<TokenData<Claim>> {
+    let header = decode_header(token)?;
+    let key = keys
+        .verification_key(header.kid.as_deref())
+        .ok_or(ErrorKind::InvalidToken)?;
+
+    let validation = Validation::new(key.algorithm);
+    decode::<Claim>(token, key.decoding_key(), &validation)
 }
 
 //password hashing using argon2
@@ -68,8 +105,44 @@ pub fn verify_hashed_password(
     argon2.verify_password(password.as_bytes(), &stored_password)
 }
 
+//spend as long as verifying a real password, then fail like a wrong one. Used when no account
+//matches, so the response time doesn't tell which addresses have one.
+pub fn verify_dummy_password(password: &str) -> argon2::password_hash::Result<()> {
+    static DUMMY_HASH: LazyLock<String> = LazyLock::new(|| {
+        generate_hash_password("dummy password").expect("argon2 hashes a constant password")
+    });
+
+    let _ = verify_hashed_password(password, &DUMMY_HASH);
+    Err(argon2::password_hash::Error::Password)
+}
+
+// address of the client, from X-Forwarded-For / Forwarded only when the proxy setting them is
+// trusted, since anyone can send those headers
+pub fn client_ip(req: &HttpRequest, trust_forwarded_for: bool) -> String {
+    let info = req.connection_info();
+    let forwarded = trust_forwarded_for
+        .then(|| info.realip_remote_addr())
+        .flatten()
+        .and_then(|addr| addr.parse::<IpAddr>().ok());
+
+    forwarded
+        .map(|ip| ip.to_string())
+        .or_else(|| info.peer_addr().map(str::to_string))
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
 // parse uuid from string
-pub fn parse_uuid(token: &str) -> Result<Uuid, actix_web::Error> {
-    Uuid::try_parse(token)
-        .map_err(|_| actix_web::error::ErrorInternalServerError("Error parsing uuid from string"))
+pub fn parse_uuid(token: &str) -> Result<Uuid, AppError> {
+    Uuid::try_parse(token).map_err(|_| AppError::Internal("Error parsing uuid from string".into()))
+}
+
+// id of the user the request was authenticated as
+pub fn claim_user_id(claim: Option<ReqData<Claim>>) -> Result<Uuid, AppError> {
+    match claim {
+        Some(claim) => parse_uuid(&claim.sub),
+        None => Err(AppError::Unauthorized(
+            "token_missing",
+            "Missing access token".to_string(),
+        )),
+    }
 }
@@ -0,0 +1,59 @@
+use chrono::{Duration, Utc};
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::{
+    email::{send_in_background, VERIFY_EMAIL},
+    queries::{insert_verification_token, last_verification_token_at},
+    utils::{generate_token, hash_token},
+    AppState,
+};
+
+// Shortest time between two verification emails to the same account
+pub const RESEND_COOLDOWN_SECS: i64 = 60;
+
+// Store a new verification token for a user, links sent earlier stop working
+pub async fn issue_verification_token(
+    conn: &mut PgConnection,
+    user_id: &Uuid,
+    ttl_hours: i64,
+) -> sqlx::Result<String> {
+    let token = generate_token();
+    let created_at = Utc::now();
+    let expires_at = created_at + Duration::hours(ttl_hours);
+
+    insert_verification_token(
+        conn,
+        &Uuid::new_v4(),
+        user_id,
+        &hash_token(&token),
+        &created_at,
+        &expires_at,
+    )
+    .await?;
+
+    Ok(token)
+}
+
+// Email a user the link to verify their address with
+pub fn send_verification_email(state: &AppState, name: &str, email: &str, token: &str) {
+    let link = format!(
+        "{}/verify-email?token={}",
+        state.settings.email.link_base_url.trim_end_matches('/'),
+        token
+    );
+    let ttl_hours = state.settings.auth.email_verification_ttl_hours.to_string();
+    let message = VERIFY_EMAIL.render(
+        email,
+        &[("name", name), ("link", &link), ("ttl_hours", &ttl_hours)],
+    );
+    send_in_background(state.email.clone(), message);
+}
+
+// Whether another verification email may be sent to a user yet
+pub async fn may_resend_verification(state: &AppState, user_id: &Uuid) -> sqlx::Result<bool> {
+    let last_sent = last_verification_token_at(&state.pool, user_id).await?;
+    Ok(last_sent
+        .map(|sent| Utc::now() - sent >= Duration::seconds(RESEND_COOLDOWN_SECS))
+        .unwrap_or(true))
+}